@@ -1,12 +1,26 @@
 use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
 use glob::Pattern;
 
+use super::embedded::EmbeddedFs;
+use super::mounts::{MountMode, MountTable};
+use super::path;
+use super::quota::{WriteLimits, WriteQuota, WriteUsage};
+use super::scratch::{self, ScratchDir, ScratchUsage};
+
 #[derive(Debug, Clone)]
 pub struct FSConfig {
     read_whitelist: HashSet<Pattern>,
     read_blacklist: HashSet<Pattern>,
     write_whitelist: HashSet<Pattern>,
     write_blacklist: HashSet<Pattern>,
+    root: Option<PathBuf>,
+    quiet: bool,
+    embedded: EmbeddedFs,
+    quota: WriteQuota,
+    scratch: Option<ScratchDir>,
+    mounts: MountTable,
 }
 
 impl Default for FSConfig {
@@ -16,45 +30,252 @@ impl Default for FSConfig {
             read_blacklist: HashSet::new(),
             write_whitelist: HashSet::new(),
             write_blacklist: HashSet::new(),
+            root: None,
+            quiet: false,
+            embedded: EmbeddedFs::default(),
+            quota: WriteQuota::default(),
+            scratch: None,
+            mounts: MountTable::default(),
         }
     }
 }
 
+/// Patterns are normalized like the paths they are matched against, so
+/// `./*` and `*` are the same pattern.
+fn pattern(pattern: &str) -> Option<Pattern> {
+    let normalized = path::normalize(Path::new(pattern));
+    Pattern::new(&normalized.to_string_lossy()).ok()
+}
+
 impl FSConfig {
 
     pub fn add_to_read_whitelist(&mut self, pattern: &str) {
-        if let Ok(p) = Pattern::new(pattern) {
+        if let Some(p) = self::pattern(pattern) {
             self.read_whitelist.insert(p);
         }
     }
 
     pub fn add_to_read_blacklist(&mut self, pattern: &str) {
-        if let Ok(p) = Pattern::new(pattern) {
+        if let Some(p) = self::pattern(pattern) {
             self.read_blacklist.insert(p);
         }
     }
 
     pub fn add_to_write_whitelist(&mut self, pattern: &str) {
-        if let Ok(p) = Pattern::new(pattern) {
+        if let Some(p) = self::pattern(pattern) {
             self.write_whitelist.insert(p);
         }
     }
 
     pub fn add_to_write_blacklist(&mut self, pattern: &str) {
-        if let Ok(p) = Pattern::new(pattern) {
+        if let Some(p) = self::pattern(pattern) {
             self.write_blacklist.insert(p);
         }
     }
 
+    /// Legacy mode where denied operations print a warning and return a
+    /// neutral value (`0`, `-1` or an empty result) instead of throwing an
+    /// `EACCES` error.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Directory relative paths are resolved against. Relative patterns
+    /// match paths relative to it, and paths that end up outside of it
+    /// (through `..` or symlinks) are only allowed by absolute patterns.
+    /// Without a root, paths are relative to the current directory.
+    pub fn set_root(&mut self, root: &str) {
+        let root = path::normalize(Path::new(root));
+        self.root = (root != Path::new(".")).then_some(root);
+    }
+
+    /// Limits on what can be written on top of the path permissions.
+    pub fn set_write_limits(&mut self, limits: WriteLimits) {
+        self.quota.limits = limits;
+    }
+
+    /// Handle on the writes counted against the limits, to reset them when
+    /// a new invocation starts.
+    pub fn write_usage(&self) -> WriteUsage {
+        self.quota.usage.clone()
+    }
+
+    pub(super) fn quota(&self) -> &WriteQuota {
+        &self.quota
+    }
+
+    /// Designates `path` as the scratch area returned by `Node.FS.tmpdir()`.
+    /// Everything below it can be read and written without a whitelist
+    /// entry (the blacklists still apply), and writes fail with `ENOSPC`
+    /// once its files would take more than `max_bytes`. A relative `path`
+    /// names a host directory (usually a preopen) that is mounted at `/tmp`.
+    pub fn set_scratch_dir(&mut self, path: &str, max_bytes: Option<u64>) -> io::Result<()> {
+        let path = path::normalize(Path::new(path));
+        let path = if path.is_relative() {
+            self.mounts.add(scratch::MOUNT_POINT, &path.to_string_lossy(), MountMode::ReadWrite)?;
+            PathBuf::from(scratch::MOUNT_POINT)
+        } else {
+            path
+        };
+        self.scratch = Some(ScratchDir {
+            path,
+            max_bytes,
+            usage: ScratchUsage::default(),
+        });
+        Ok(())
+    }
+
+    /// Removes the files of the scratch area, to be called when an invocation
+    /// returns.
+    pub fn wipe_scratch(&self) -> io::Result<()> {
+        match &self.scratch {
+            Some(scratch) => {
+                scratch.usage.reset();
+                scratch::wipe(&self.mounts.to_host(&scratch.path))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Absolute path of the scratch area seen by JS.
+    pub(super) fn scratch_path(&self) -> Option<PathBuf> {
+        self.scratch.as_ref().map(|scratch| scratch.path.clone())
+    }
+
+    /// The usage and size limit of the scratch area when `resolved` is inside
+    /// it.
+    pub(super) fn scratch_limit(&self, resolved: &Path) -> Option<(&ScratchUsage, u64)> {
+        let scratch = self.scratch.as_ref()?;
+        let max_bytes = scratch.max_bytes?;
+        let dir = self.mounts.to_host(&scratch.path);
+        resolved.starts_with(dir).then_some((&scratch.usage, max_bytes))
+    }
+
+    /// Reads the files under `host_path` into memory and serves them under
+    /// `virtual_path`. Embedded paths are readable without being whitelisted
+    /// and writing to them fails with `EROFS`.
+    pub fn embed_dir(&mut self, host_path: &Path, virtual_path: &str) -> io::Result<()> {
+        self.embedded.add_dir(host_path, virtual_path)
+    }
+
+    pub(super) fn embedded(&self) -> &EmbeddedFs {
+        &self.embedded
+    }
+
+    /// Serves the host directory `host_path` (usually a preopen) under the
+    /// absolute `virtual_path`. Patterns match the virtual paths, and with
+    /// [`MountMode::ReadOnly`] writes below the mount fail with `EROFS`
+    /// whatever the write lists say. Paths outside of every mount keep
+    /// addressing the host directly, except the host directories of the
+    /// mounts, which are denied so that they can't bypass the mode.
+    pub fn add_mount(&mut self, virtual_path: &str, host_path: &str, mode: MountMode) -> io::Result<()> {
+        self.mounts.add(virtual_path, host_path, mode)
+    }
+
+    /// Whether `path` is below a read-only mount.
+    pub(super) fn is_read_only_mount(&self, path: &str) -> bool {
+        self.mounts.is_read_only(&self.lexical(path))
+    }
+
+    /// Path to show to JS for a host path returned by `resolve_*`.
+    pub(super) fn virtual_path(&self, host_path: &Path) -> PathBuf {
+        self.mounts.to_virtual(host_path)
+    }
+
     pub fn can_read(&self, path: &str) -> bool {
-        let whitelisted = self.read_whitelist.iter().any(|pattern| pattern.matches(path));
-        let blacklisted = self.read_blacklist.iter().any(|pattern| pattern.matches(path));
-        whitelisted && !blacklisted
+        self.resolve_read(path).is_ok()
     }
 
     pub fn can_write(&self, path: &str) -> bool {
-        let whitelisted = self.write_whitelist.iter().any(|pattern| pattern.matches(path));
-        let blacklisted = self.write_blacklist.iter().any(|pattern| pattern.matches(path));
-        whitelisted  && !blacklisted
+        self.resolve_write(path).is_ok()
+    }
+
+    /// Returns the host path to use to read `path`, or the canonical path
+    /// that was denied.
+    pub fn resolve_read(&self, path: &str) -> Result<PathBuf, PathBuf> {
+        self.resolve(path, &self.read_whitelist, &self.read_blacklist)
+    }
+
+    /// Returns the host path to use to write `path`, or the canonical path
+    /// that was denied.
+    pub fn resolve_write(&self, path: &str) -> Result<PathBuf, PathBuf> {
+        self.resolve(path, &self.write_whitelist, &self.write_blacklist)
+    }
+
+    fn resolve(
+        &self,
+        path: &str,
+        whitelist: &HashSet<Pattern>,
+        blacklist: &HashSet<Pattern>,
+    ) -> Result<PathBuf, PathBuf> {
+        let lexical = self.lexical(path);
+        if !self.is_allowed(&lexical, whitelist, blacklist) {
+            return Err(lexical);
+        }
+
+        // The target of symlinks must be allowed too, under its virtual
+        // path. Errors other than missing files (which `resolve_symlinks`
+        // tolerates) are left to the actual operation to report.
+        let host = self.mounts.to_host(&lexical);
+        if let Ok(real) = path::resolve_symlinks(&host) {
+            let real = self.mounts.to_virtual(&real);
+            if real != lexical && !self.is_allowed(&real, whitelist, blacklist) {
+                return Err(real);
+            }
+        }
+        Ok(host)
+    }
+
+    /// `path` normalized and joined to the root, the form that patterns and
+    /// mount points are matched against.
+    fn lexical(&self, path: &str) -> PathBuf {
+        match &self.root {
+            Some(root) => path::normalize(&root.join(path)),
+            None => path::normalize(Path::new(path)),
+        }
+    }
+
+    fn is_allowed(&self, path: &Path, whitelist: &HashSet<Pattern>, blacklist: &HashSet<Pattern>) -> bool {
+        // Absolute patterns match the absolute path, relative patterns match
+        // the path relative to the root when it is in it
+        let absolute = path
+            .is_absolute()
+            .then(|| path.to_string_lossy().to_string());
+        let relative = match &self.root {
+            Some(root) => path.strip_prefix(root).ok().map(path::normalize),
+            None => path.is_relative().then(|| path.to_path_buf()),
+        }
+        .map(|p| p.to_string_lossy().to_string());
+
+        let matches = |patterns: &HashSet<Pattern>| {
+            patterns.iter().any(|pattern| {
+                let form = if Path::new(pattern.as_str()).is_absolute() {
+                    &absolute
+                } else {
+                    &relative
+                };
+                form.as_ref().is_some_and(|form| pattern.matches(form))
+            })
+        };
+        if matches(blacklist) {
+            return false;
+        }
+        // Mounted host directories are only reachable through the mount
+        if self.mounts.is_host_path(path) {
+            return false;
+        }
+        // The scratch area needs no whitelist entry
+        if self.scratch_path().is_some_and(|dir| path.starts_with(dir)) {
+            return true;
+        }
+        // Relative paths escaping the current directory are never matched
+        if path.is_relative() && path::escapes(path) {
+            return false;
+        }
+        matches(whitelist)
     }
 }
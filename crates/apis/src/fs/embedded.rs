@@ -0,0 +1,123 @@
+//! Read-only files embedded into the module with `javy compile --embed-dir`.
+//!
+//! The files are read while the module is pre-initialized by Wizer, so once
+//! the snapshot is taken their contents live in the module's data segment
+//! and the host does not need to preopen anything. Embedded paths are served
+//! before the host filesystem, are always readable and are never writable.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::path;
+
+pub(super) enum Entry<'a> {
+    File(&'a [u8]),
+    Directory,
+}
+
+#[derive(Clone, Default)]
+struct Tree {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    directories: BTreeSet<PathBuf>,
+    // The virtual paths given to `add_dir`, nothing can be created below them
+    roots: BTreeSet<PathBuf>,
+}
+
+// `FSConfig` is cloned for every native function, share the contents
+#[derive(Clone, Default)]
+pub(super) struct EmbeddedFs {
+    tree: Arc<Tree>,
+}
+
+impl fmt::Debug for EmbeddedFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmbeddedFs")
+            .field("roots", &self.tree.roots)
+            .field("files", &self.tree.files.len())
+            .finish()
+    }
+}
+
+impl EmbeddedFs {
+    /// Recursively reads the files of `host_path` and serves them under
+    /// `virtual_path`.
+    pub(super) fn add_dir(&mut self, host_path: &Path, virtual_path: &str) -> io::Result<()> {
+        let root = path::normalize(Path::new(virtual_path));
+        self.add_entries(host_path, &root)?;
+        let tree = Arc::make_mut(&mut self.tree);
+        tree.add_directory(&root);
+        tree.roots.insert(root);
+        Ok(())
+    }
+
+    fn add_entries(&mut self, host_path: &Path, virtual_path: &Path) -> io::Result<()> {
+        for entry in std::fs::read_dir(host_path)? {
+            let host_child = entry?.path();
+            let Some(name) = host_child.file_name() else {
+                continue;
+            };
+            let virtual_child = virtual_path.join(name);
+            // Follow symlinks, the embedded tree has none
+            if std::fs::metadata(&host_child)?.is_dir() {
+                Arc::make_mut(&mut self.tree).add_directory(&virtual_child);
+                self.add_entries(&host_child, &virtual_child)?;
+            } else {
+                let contents = std::fs::read(&host_child)?;
+                let tree = Arc::make_mut(&mut self.tree);
+                tree.add_directory(virtual_path);
+                tree.files.insert(virtual_child, contents);
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn lookup(&self, path: &str) -> Option<Entry<'_>> {
+        let path = path::normalize(Path::new(path));
+        if let Some(contents) = self.tree.files.get(&path) {
+            Some(Entry::File(contents))
+        } else if self.tree.directories.contains(&path) {
+            Some(Entry::Directory)
+        } else {
+            None
+        }
+    }
+
+    /// Sorted names of the entries of an embedded directory.
+    pub(super) fn read_dir(&self, path: &str) -> Option<Vec<String>> {
+        let path = path::normalize(Path::new(path));
+        if !self.tree.directories.contains(&path) {
+            return None;
+        }
+        let children = self.tree.files.keys().chain(self.tree.directories.iter());
+        let mut names: Vec<String> = children
+            .filter(|child| child.parent() == Some(path.as_path()))
+            .filter_map(|child| child.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        Some(names)
+    }
+
+    /// Whether `path` is embedded or would be created inside an embedded
+    /// directory.
+    pub(super) fn is_read_only(&self, path: &str) -> bool {
+        let path = path::normalize(Path::new(path));
+        self.tree.files.contains_key(&path)
+            || path.ancestors().any(|p| self.tree.roots.contains(p))
+    }
+}
+
+impl Tree {
+    /// Adds `path` and its parents, except for `/` and `.` which stay backed
+    /// by the host.
+    fn add_directory(&mut self, path: &Path) {
+        for dir in path.ancestors() {
+            if dir.parent().is_none() || dir.as_os_str().is_empty() || dir == Path::new(".") {
+                break;
+            }
+            self.directories.insert(dir.to_path_buf());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! The string encodings of Node's `Buffer` that `Node.FS` accepts as its
+//! `encoding` option.
+use anyhow::{bail, Result};
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Base64,
+    Hex,
+    Ascii,
+}
+
+impl TryFrom<&str> for Encoding {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Ok(Encoding::Utf8),
+            "latin1" | "binary" => Ok(Encoding::Latin1),
+            "base64" => Ok(Encoding::Base64),
+            "hex" => Ok(Encoding::Hex),
+            "ascii" => Ok(Encoding::Ascii),
+            _ => bail!("Unknown encoding: {}", value),
+        }
+    }
+}
+
+impl Encoding {
+    /// Turns the contents of a file into a string.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            // Invalid sequences become U+FFFD like in Node
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Ascii => bytes.iter().map(|&b| (b & 0x7f) as char).collect(),
+            Encoding::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            Encoding::Base64 => encode_base64(bytes),
+        }
+    }
+
+    /// Turns a string into the bytes to write. Like Node, characters that
+    /// don't fit are truncated and invalid hex or base64 input is skipped.
+    pub fn encode(&self, string: &str) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => string.as_bytes().to_vec(),
+            Encoding::Latin1 | Encoding::Ascii => string.chars().map(|c| c as u32 as u8).collect(),
+            Encoding::Hex => decode_hex(string),
+            Encoding::Base64 => decode_base64(string),
+        }
+    }
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | ((b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn decode_base64(string: &str) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(string.len() / 4 * 3);
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in string.bytes() {
+        // The URL safe alphabet is accepted too
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => continue,
+        };
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((n >> bits) as u8);
+        }
+    }
+    decoded
+}
+
+fn decode_hex(string: &str) -> Vec<u8> {
+    string
+        .as_bytes()
+        .chunks_exact(2)
+        .map_while(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+
+    #[test]
+    fn test_encodings() {
+        let bytes = b"hi\xff!";
+        assert_eq!(Encoding::Hex.decode(bytes), "6869ff21");
+        assert_eq!(Encoding::Base64.decode(bytes), "aGn/IQ==");
+        assert_eq!(Encoding::Latin1.decode(bytes), "hi\u{ff}!");
+        assert_eq!(Encoding::Ascii.decode(bytes), "hi\u{7f}!");
+        assert_eq!(Encoding::Utf8.decode(bytes), "hi\u{fffd}!");
+
+        assert_eq!(Encoding::Hex.encode("6869ff21zz"), bytes);
+        assert_eq!(Encoding::Base64.encode("aGn/IQ=="), bytes);
+        assert_eq!(Encoding::Base64.encode("aGn_IQ"), bytes);
+        assert_eq!(Encoding::Latin1.encode("hi\u{ff}!"), bytes);
+        assert_eq!(Encoding::Utf8.encode("é"), "é".as_bytes());
+        assert!(Encoding::try_from("utf16").is_err());
+    }
+}
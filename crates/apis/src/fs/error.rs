@@ -0,0 +1,110 @@
+//! Node-compatible errors for `Node.FS`.
+//!
+//! Errors are thrown from the native callbacks with Node's message format,
+//! `CODE: description, syscall 'path'`, and `fs.js` turns them back into
+//! `Error` objects carrying `code`, `syscall` and `path` properties.
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+use javy::quickjs::JSError;
+
+#[derive(Debug)]
+pub struct FsError {
+    code: &'static str,
+    syscall: String,
+    path: Option<String>,
+}
+
+impl FsError {
+    pub fn new(code: &'static str, syscall: &str, path: Option<&str>) -> Self {
+        Self {
+            code,
+            syscall: syscall.to_string(),
+            path: path.map(str::to_string),
+        }
+    }
+
+    /// The access was denied by [`super::FSConfig`].
+    pub fn access_denied(syscall: &str, path: &str) -> Self {
+        Self::new("EACCES", syscall, Some(path))
+    }
+
+    /// A limit of [`super::WriteLimits`] would be exceeded.
+    pub fn quota_exceeded(syscall: &str, path: Option<&str>) -> Self {
+        Self::new("EDQUOT", syscall, path)
+    }
+
+    pub fn bad_fd(syscall: &str) -> Self {
+        Self::new("EBADF", syscall, None)
+    }
+
+    /// Maps an error returned by the host filesystem.
+    pub fn from_io(err: &io::Error, syscall: &str, path: Option<&str>) -> Self {
+        let code = match err.kind() {
+            ErrorKind::NotFound => "ENOENT",
+            ErrorKind::PermissionDenied => "EACCES",
+            ErrorKind::AlreadyExists => "EEXIST",
+            ErrorKind::IsADirectory => "EISDIR",
+            ErrorKind::NotADirectory => "ENOTDIR",
+            ErrorKind::DirectoryNotEmpty => "ENOTEMPTY",
+            ErrorKind::ReadOnlyFilesystem => "EROFS",
+            ErrorKind::StorageFull => "ENOSPC",
+            ErrorKind::FileTooLarge => "EFBIG",
+            ErrorKind::CrossesDevices => "EXDEV",
+            ErrorKind::InvalidInput => "EINVAL",
+            ErrorKind::Unsupported => "ENOTSUP",
+            _ => "EIO",
+        };
+        Self::new(code, syscall, path)
+    }
+
+    fn description(&self) -> &'static str {
+        match self.code {
+            "ENOENT" => "no such file or directory",
+            "EACCES" => "permission denied",
+            "EEXIST" => "file already exists",
+            "EISDIR" => "illegal operation on a directory",
+            "ENOTDIR" => "not a directory",
+            "ENOTEMPTY" => "directory not empty",
+            "EROFS" => "read-only file system",
+            "ENOSPC" => "no space left on device",
+            "EFBIG" => "file too large",
+            "EXDEV" => "cross-device link not permitted",
+            "EINVAL" => "invalid argument",
+            "ENOTSUP" => "operation not supported",
+            "EBADF" => "bad file descriptor",
+            "EDQUOT" => "disk quota exceeded",
+            _ => "i/o error",
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}, {}", self.code, self.description(), self.syscall)?;
+        if let Some(path) = &self.path {
+            write!(f, " '{}'", path)?;
+        }
+        Ok(())
+    }
+}
+
+// Not implementing `std::error::Error` keeps this from overlapping with
+// anyhow's blanket conversion.
+impl From<FsError> for anyhow::Error {
+    fn from(err: FsError) -> Self {
+        // `wrap_callback` only keeps the message verbatim for `JSError`s
+        JSError::Internal(err.to_string()).into()
+    }
+}
+
+/// Extension to map [`io::Result`]s into [`FsError`]s.
+pub trait IoResultExt<T> {
+    fn fs_err(self, syscall: &str, path: &str) -> anyhow::Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn fs_err(self, syscall: &str, path: &str) -> anyhow::Result<T> {
+        self.map_err(|e| FsError::from_io(&e, syscall, Some(path)).into())
+    }
+}
@@ -1,11 +1,425 @@
 (function () {
-    const __writeFileSync = globalThis.__writeFileSync;
-    const __readFileSync = globalThis.__readFileSync;
-    const __statSync = globalThis.__statSync;
-    const __openwrite = globalThis.__openwrite;
+    // Native errors are thrown with Node's message format
+    // `CODE: description, syscall 'path'`, rebuild the matching Node error.
+    const NODE_ERROR_MESSAGE = /^(E[A-Z]+): [^,]+, (\w+)(?: '(.*)')?$/;
 
+    function withNodeErrors(native) {
+        return function (...args) {
+            try {
+                return native(...args);
+            } catch (e) {
+                const match = NODE_ERROR_MESSAGE.exec(e.message);
+                if (match === null) {
+                    throw e;
+                }
+                const error = new Error(e.message);
+                error.code = match[1];
+                error.syscall = match[2];
+                if (match[3] !== undefined) {
+                    error.path = match[3];
+                }
+                throw error;
+            }
+        };
+    }
+
+    const __writeFileSync = withNodeErrors(globalThis.__writeFileSync);
+    const __writeFileStringSync = withNodeErrors(globalThis.__writeFileStringSync);
+    const __readFileSync = withNodeErrors(globalThis.__readFileSync);
+    const __readFileStringSync = withNodeErrors(globalThis.__readFileStringSync);
+    const __statSync = withNodeErrors(globalThis.__statSync);
+    const __openwrite = withNodeErrors(globalThis.__openwrite);
+    const __readdirSync = withNodeErrors(globalThis.__readdirSync);
+    const __mkdirSync = withNodeErrors(globalThis.__mkdirSync);
+    const __mkdtempSync = withNodeErrors(globalThis.__mkdtempSync);
+    const __tmpdir = globalThis.__tmpdir;
+    const __rmSync = withNodeErrors(globalThis.__rmSync);
+    const __unlinkSync = withNodeErrors(globalThis.__unlinkSync);
+    const __renameSync = withNodeErrors(globalThis.__renameSync);
+    const __openSync = withNodeErrors(globalThis.__openSync);
+    const __readSync = withNodeErrors(globalThis.__readSync);
+    const __writeSync = withNodeErrors(globalThis.__writeSync);
+    const __writeStringSync = withNodeErrors(globalThis.__writeStringSync);
+    const __fstatSync = withNodeErrors(globalThis.__fstatSync);
+    const __closeSync = withNodeErrors(globalThis.__closeSync);
+
+
+    // Every flag accepted by `FileFlag::parse`, keep both in sync
+    const FLAGS = [
+        'r', 'rs', 'sr', 'r+', 'rs+', 'sr+',
+        'w', 'wx', 'xw', 'w+', 'wx+', 'xw+',
+        'a', 'ax', 'xa', 'as', 'sa', 'a+', 'ax+', 'xa+', 'as+', 'sa+',
+    ];
+
+    // Node's options are an encoding or an object, a flag used to be passed
+    // in their place and is still accepted
+    function fileOptions(options, defaultFlag) {
+        if (typeof options === 'string') {
+            return FLAGS.includes(options)
+                ? { flag: options, encoding: null }
+                : { flag: defaultFlag, encoding: options };
+        }
+        if (typeof options === 'object' && options !== null) {
+            return { flag: options.flag || defaultFlag, encoding: options.encoding || null };
+        }
+        return { flag: defaultFlag, encoding: null };
+    }
+
+    const STATS_NUMBER_FIELDS = [
+        'dev', 'ino', 'mode', 'nlink', 'uid', 'gid', 'size',
+        'atimeMs', 'mtimeMs', 'ctimeMs', 'birthtimeMs',
+    ];
+
+    class Stats {
+        constructor(raw, bigint) {
+            for (const field of STATS_NUMBER_FIELDS) {
+                this[field] = bigint ? BigInt(Math.trunc(raw[field])) : raw[field];
+            }
+            this.atime = new Date(raw.atimeMs);
+            this.mtime = new Date(raw.mtimeMs);
+            this.ctime = new Date(raw.ctimeMs);
+            this.birthtime = new Date(raw.birthtimeMs);
+            Object.defineProperty(this, '_type', { value: raw.type, enumerable: false });
+        }
+
+        isFile() {
+            return this._type === 'file';
+        }
+
+        isDirectory() {
+            return this._type === 'directory';
+        }
+
+        isSymbolicLink() {
+            return this._type === 'symlink';
+        }
+    }
+
+    function toStats(raw, options) {
+        // In quiet mode denied calls only return `{ size: -1 }`
+        if (raw.type === undefined) {
+            return raw;
+        }
+        let bigint = typeof options === 'object' && options !== null && !!options.bigint;
+        return new Stats(raw, bigint);
+    }
+
+    // Callbacks and events of the streams run as microtasks, which the event
+    // loop of the runtime drains
+    function nextTick(callback) {
+        Promise.resolve().then(callback);
+    }
+
+    class Emitter {
+        constructor() {
+            Object.defineProperty(this, '_listeners', { value: {}, enumerable: false });
+        }
+
+        on(event, listener) {
+            (this._listeners[event] = this._listeners[event] || []).push(listener);
+            return this;
+        }
+
+        once(event, listener) {
+            const wrapper = (...args) => {
+                this.off(event, wrapper);
+                listener(...args);
+            };
+            return this.on(event, wrapper);
+        }
+
+        off(event, listener) {
+            const listeners = this._listeners[event];
+            if (listeners !== undefined) {
+                this._listeners[event] = listeners.filter((l) => l !== listener);
+            }
+            return this;
+        }
+
+        emit(event, ...args) {
+            const listeners = this._listeners[event];
+            if (listeners === undefined || listeners.length === 0) {
+                return false;
+            }
+            for (const listener of [...listeners]) {
+                listener(...args);
+            }
+            return true;
+        }
+    }
+
+    class ReadStream extends Emitter {
+        constructor(path, options) {
+            super();
+            options = options || {};
+            this.path = path;
+            this.flags = options.flags || 'r';
+            this.highWaterMark = options.highWaterMark || 64 * 1024;
+            this.start = options.start || 0;
+            // Inclusive, like in Node
+            this.end = options.end === undefined ? Infinity : options.end;
+            this.bytesRead = 0;
+            this.destroyed = false;
+            this._position = this.start;
+            this._flowing = false;
+        }
+
+        // Reads the next chunk from the file, `null` at the end
+        _read() {
+            if (this.destroyed || this._position > this.end) {
+                return null;
+            }
+            const length = Math.min(this.highWaterMark, this.end - this._position + 1);
+            const [n, data] = Node.FS.readFileChunk(this.path, this._position, length, this.flags);
+            if (n === 0) {
+                return null;
+            }
+            this._position += n;
+            this.bytesRead += n;
+            return data.subarray(0, n);
+        }
+
+        _flow() {
+            nextTick(() => {
+                let chunk;
+                try {
+                    chunk = this._read();
+                } catch (e) {
+                    this.destroy(e);
+                    return;
+                }
+                if (chunk === null) {
+                    if (!this.destroyed) {
+                        this.emit('end');
+                        this.destroy();
+                    }
+                    return;
+                }
+                this.emit('data', chunk);
+                this._flow();
+            });
+        }
+
+        on(event, listener) {
+            super.on(event, listener);
+            // Attaching a `data` listener starts reading
+            if (event === 'data' && !this._flowing) {
+                this._flowing = true;
+                this._flow();
+            }
+            return this;
+        }
+
+        destroy(error) {
+            if (!this.destroyed) {
+                this.destroyed = true;
+                if (error) {
+                    this.emit('error', error);
+                }
+                this.emit('close');
+            }
+            return this;
+        }
+
+        close(callback) {
+            if (callback) {
+                this.once('close', callback);
+            }
+            return this.destroy();
+        }
+
+        [Symbol.asyncIterator]() {
+            return {
+                next: () => new Promise((resolve) => {
+                    let chunk;
+                    try {
+                        chunk = this._read();
+                    } catch (e) {
+                        this.destroy();
+                        throw e;
+                    }
+                    if (chunk === null) {
+                        this.destroy();
+                        resolve({ done: true, value: undefined });
+                    } else {
+                        resolve({ done: false, value: chunk });
+                    }
+                }),
+                return: () => {
+                    this.destroy();
+                    return Promise.resolve({ done: true, value: undefined });
+                },
+                [Symbol.asyncIterator]() {
+                    return this;
+                },
+            };
+        }
+    }
+
+    class WriteStream extends Emitter {
+        constructor(path, options) {
+            super();
+            options = typeof options === 'string' ? { encoding: options } : (options || {});
+            this.path = path;
+            this.flags = options.flags || 'w';
+            this.encoding = options.encoding || 'utf8';
+            this.bytesWritten = 0;
+            this.writable = true;
+            this.destroyed = false;
+            // `null` writes at the current position
+            this._position = options.start === undefined ? null : options.start;
+            this.fd = Node.FS.openSync(path, this.flags);
+        }
+
+        write(chunk, encoding, callback) {
+            if (typeof encoding === 'function') {
+                callback = encoding;
+                encoding = undefined;
+            }
+            if (!this.writable) {
+                const error = new Error('write after end');
+                error.code = 'ERR_STREAM_WRITE_AFTER_END';
+                nextTick(() => {
+                    if (callback) {
+                        callback(error);
+                    }
+                    this.emit('error', error);
+                });
+                return false;
+            }
+
+            let n;
+            try {
+                n = typeof chunk === 'string'
+                    ? Node.FS.writeSync(this.fd, chunk, this._position, encoding || this.encoding)
+                    : Node.FS.writeSync(this.fd, chunk, 0, chunk.byteLength, this._position);
+            } catch (e) {
+                nextTick(() => {
+                    if (callback) {
+                        callback(e);
+                    }
+                    this.destroy(e);
+                });
+                return false;
+            }
+            this.bytesWritten += n;
+            if (this._position !== null) {
+                this._position += n;
+            }
+            if (callback) {
+                nextTick(() => callback(null));
+            }
+            return true;
+        }
+
+        end(chunk, encoding, callback) {
+            if (typeof chunk === 'function') {
+                callback = chunk;
+                chunk = undefined;
+            } else if (typeof encoding === 'function') {
+                callback = encoding;
+                encoding = undefined;
+            }
+            if (chunk !== undefined && chunk !== null) {
+                this.write(chunk, encoding);
+            }
+            if (this.writable) {
+                this.writable = false;
+                if (callback) {
+                    this.once('finish', callback);
+                }
+                nextTick(() => {
+                    if (!this.destroyed) {
+                        this.emit('finish');
+                        this.destroy();
+                    }
+                });
+            }
+            return this;
+        }
+
+        destroy(error) {
+            if (!this.destroyed) {
+                this.destroyed = true;
+                this.writable = false;
+                // Quiet denials open no file
+                if (this.fd >= 0) {
+                    Node.FS.closeSync(this.fd);
+                }
+                if (error) {
+                    this.emit('error', error);
+                }
+                this.emit('close');
+            }
+            return this;
+        }
+
+        close(callback) {
+            if (callback) {
+                this.once('close', callback);
+            }
+            return this.end();
+        }
+    }
+
+    // The promise API runs the synchronous functions as microtasks, so they
+    // settle when the runtime drains its pending jobs
+    function deferred(fn) {
+        return (...args) => Promise.resolve().then(() => fn(...args));
+    }
+
+    class FileHandle {
+        constructor(fd) {
+            this.fd = fd;
+        }
+
+        read(buffer, offset, length, position) {
+            return deferred(() => {
+                const bytesRead = Node.FS.readSync(this.fd, buffer, offset, length, position);
+                return { bytesRead, buffer };
+            })();
+        }
+
+        write(buffer, offset, length, position) {
+            return deferred(() => {
+                const bytesWritten = Node.FS.writeSync(this.fd, buffer, offset, length, position);
+                return { bytesWritten, buffer };
+            })();
+        }
+
+        stat(options) {
+            return deferred(() => Node.FS.fstatSync(this.fd, options))();
+        }
+
+        close() {
+            return deferred(() => Node.FS.closeSync(this.fd))();
+        }
+    }
+
+    function stat(filename, options, followSymlinks) {
+        try {
+            return toStats(__statSync(filename, followSymlinks), options);
+        } catch (e) {
+            if (e.code === 'ENOENT' && typeof options === 'object' && options !== null && options.throwIfNoEntry === false) {
+                return undefined;
+            }
+            throw e;
+        }
+    }
 
     globalThis.Node.FS = {
+        // Same values as the `O_*` constants of mod.rs
+        constants: {
+            O_RDONLY: 0,
+            O_WRONLY: 1,
+            O_RDWR: 2,
+            O_CREAT: 64,
+            O_EXCL: 128,
+            O_TRUNC: 512,
+            O_APPEND: 1024,
+            O_DSYNC: 4096,
+            O_SYNC: 1052672,
+        },
         readFileChunk(filename, offset, length, flag){
             let data = new Uint8Array(length);
 
@@ -34,11 +448,13 @@
                 flag
             );
         },
-        readFileSync(filename, flag){
+        readFileSync(filename, options){
+            let { flag, encoding } = fileOptions(options, 'r');
+            if (encoding !== null) {
+                return __readFileStringSync(filename, flag, encoding);
+            }
 
-            let meta = __statSync(filename, 
-                flag
-                );
+            let meta = __statSync(filename, true);
             let size = meta.size;
             let data = new Uint8Array(size);
 
@@ -53,30 +469,140 @@
                 0,
                 // File write options, like "w" or "a"
                 flag,
-                // We just ignore the mode, this will run inside the isolated VM, there
-                // is no reason to allow other mode
+                // The raw bytes are returned, encodings are handled above
                 'utf8'
             );
 
             return data
         },
-        statSync(filename, flag){
-            return __statSync(
-                // just the filename
-                filename,
-                flag
-            );
+        // The second argument used to be an open flag, strings are still
+        // accepted and ignored
+        statSync(filename, options){
+            return stat(filename, options, true);
+        },
+        lstatSync(filename, options){
+            return stat(filename, options, false);
+        },
+        existsSync(filename){
+            try {
+                // Quiet denials report a negative size
+                return __statSync(filename, true).size >= 0;
+            } catch (e) {
+                return false;
+            }
+        },
+        readdirSync(path){
+            return __readdirSync(path);
         },
         listdir(path){
-            // TODO
+            return this.readdirSync(path);
+        },
+        mkdirSync(path, options){
+            // Node also accepts the mode as the second argument, it is ignored here
+            let recursive = typeof options === 'object' && options !== null && !!options.recursive;
+            return __mkdirSync(path, recursive);
+        },
+        // The scratch area, writable without permissions and wiped after
+        // every invocation
+        tmpdir(){
+            return __tmpdir();
+        },
+        mkdtempSync(prefix){
+            return __mkdtempSync(prefix);
+        },
+        rmSync(path, options){
+            options = options || {};
+            return __rmSync(path, !!options.recursive, !!options.force);
+        },
+        unlinkSync(path){
+            return __unlinkSync(path);
+        },
+        renameSync(oldPath, newPath){
+            return __renameSync(oldPath, newPath);
+        },
+        promises: {
+            readFile: deferred((path, options) => Node.FS.readFileSync(path, options)),
+            writeFile: deferred((path, data, options) => Node.FS.writeFileSync(path, data, options)),
+            stat: deferred((path, options) => Node.FS.statSync(path, options)),
+            lstat: deferred((path, options) => Node.FS.lstatSync(path, options)),
+            readdir: deferred((path) => Node.FS.readdirSync(path)),
+            mkdir: deferred((path, options) => Node.FS.mkdirSync(path, options)),
+            mkdtemp: deferred((prefix) => Node.FS.mkdtempSync(prefix)),
+            rm: deferred((path, options) => Node.FS.rmSync(path, options)),
+            unlink: deferred((path) => Node.FS.unlinkSync(path)),
+            rename: deferred((oldPath, newPath) => Node.FS.renameSync(oldPath, newPath)),
+            open: deferred((path, flag) => new FileHandle(Node.FS.openSync(path, flag))),
+        },
+        createReadStream(path, options){
+            return new ReadStream(path, options);
+        },
+        createWriteStream(path, options){
+            return new WriteStream(path, options);
+        },
+        openSync(filename, flag){
+            return __openSync(filename, flag || 'r');
+        },
+        readSync(fd, buffer, offset, length, position){
+            if (!(buffer instanceof Uint8Array)) {
+                throw TypeError("Buffer needs to be an Uint8Array");
+            }
+            offset = offset || 0;
+            length = length === undefined ? buffer.byteLength - offset : length;
+            // null or undefined reads from the current position
+            position = (position === null || position === undefined) ? -1 : position;
+
+            return __readSync(
+                fd,
+                buffer.buffer,
+                buffer.byteOffset + offset,
+                length,
+                position
+            );
+        },
+        writeSync(fd, buffer, offset, length, position){
+            // Node's `writeSync(fd, string, position, encoding)`
+            if (typeof buffer === 'string') {
+                position = (offset === null || offset === undefined) ? -1 : offset;
+                return __writeStringSync(fd, buffer, length || 'utf8', position);
+            }
+            if (!(buffer instanceof Uint8Array)) {
+                throw TypeError("Buffer needs to be an Uint8Array");
+            }
+            offset = offset || 0;
+            length = length === undefined ? buffer.byteLength - offset : length;
+            // null or undefined writes at the current position
+            position = (position === null || position === undefined) ? -1 : position;
+
+            return __writeSync(
+                fd,
+                buffer.buffer,
+                buffer.byteOffset + offset,
+                length,
+                position
+            );
+        },
+        fstatSync(fd, options){
+            return toStats(__fstatSync(fd), options);
+        },
+        closeSync(fd){
+            return __closeSync(fd);
         },
         writeFileSync(filename, data, offset, flag) {
+            let encoding = 'utf8';
+            if (typeof offset !== 'number' && offset !== undefined) {
+                ({ flag, encoding } = fileOptions(offset, 'w'));
+                offset = 0;
+            }
+            offset = offset || 0;
+            flag = flag || 'w';
+
+            if (typeof data === 'string') {
+                return __writeFileStringSync(filename, data, encoding || 'utf8', offset, flag);
+            }
             if (!(data instanceof Uint8Array)) {
-                throw TypeError("Data needs to be an Uint8Array");
+                throw TypeError("Data needs to be a string or an Uint8Array");
             }
 
-            console.log("L", data.length)
-             
             return __writeFileSync(
                 // just the filename
                 filename,
@@ -94,7 +620,22 @@
   
     Reflect.deleteProperty(globalThis, "__writeFileSync");
     Reflect.deleteProperty(globalThis, "__readFileSync");
+    Reflect.deleteProperty(globalThis, "__writeFileStringSync");
+    Reflect.deleteProperty(globalThis, "__readFileStringSync");
     Reflect.deleteProperty(globalThis, "__statSync");
     Reflect.deleteProperty(globalThis, "__openwrite");
+    Reflect.deleteProperty(globalThis, "__readdirSync");
+    Reflect.deleteProperty(globalThis, "__mkdirSync");
+    Reflect.deleteProperty(globalThis, "__mkdtempSync");
+    Reflect.deleteProperty(globalThis, "__tmpdir");
+    Reflect.deleteProperty(globalThis, "__rmSync");
+    Reflect.deleteProperty(globalThis, "__unlinkSync");
+    Reflect.deleteProperty(globalThis, "__renameSync");
+    Reflect.deleteProperty(globalThis, "__openSync");
+    Reflect.deleteProperty(globalThis, "__readSync");
+    Reflect.deleteProperty(globalThis, "__writeSync");
+    Reflect.deleteProperty(globalThis, "__writeStringSync");
+    Reflect.deleteProperty(globalThis, "__fstatSync");
+    Reflect.deleteProperty(globalThis, "__closeSync");
   })();
   
\ No newline at end of file
@@ -0,0 +1,65 @@
+//! Table of open files backing the file-descriptor based `Node.FS` functions
+//! (`openSync`, `readSync`, `writeSync`, `fstatSync` and `closeSync`).
+//!
+//! Permissions are checked once against [`super::FSConfig`] when a file is
+//! opened, the integer fds handed to JS only index this table.
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+
+use super::FileFlag;
+
+// 0, 1 and 2 are the standard streams in Node, don't hand them out
+const FIRST_FD: i32 = 3;
+
+#[derive(Debug)]
+struct OpenFile {
+    file: File,
+    flag: FileFlag,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+pub(super) struct FileTable {
+    next_fd: i32,
+    files: HashMap<i32, OpenFile>,
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        Self {
+            next_fd: FIRST_FD,
+            files: HashMap::new(),
+        }
+    }
+}
+
+impl FileTable {
+    /// Stores the file and returns the fd that refers to it.
+    pub(super) fn insert(&mut self, file: File, flag: FileFlag, path: PathBuf) -> i32 {
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.files.insert(fd, OpenFile { file, flag, path });
+        fd
+    }
+
+    pub(super) fn get_mut(&mut self, fd: i32) -> Option<&mut File> {
+        self.files.get_mut(&fd).map(|open| &mut open.file)
+    }
+
+    /// The flag the file was opened with and its resolved path.
+    pub(super) fn info(&self, fd: i32) -> Option<(FileFlag, PathBuf)> {
+        self.files.get(&fd).map(|open| (open.flag, open.path.clone()))
+    }
+
+    /// Closes the file by dropping its handle, returns `false` if the fd is
+    /// not open.
+    pub(super) fn close(&mut self, fd: i32) -> bool {
+        self.files.remove(&fd).is_some()
+    }
+
+    /// Closes all the files that JS did not close.
+    pub(super) fn close_all(&mut self) {
+        self.files.clear();
+    }
+}
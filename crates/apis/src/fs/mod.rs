@@ -10,74 +10,334 @@ use std::io::{Read, Write};
 use std::io::Seek;
 use javy::{
     Runtime, 
-    quickjs::JSValue,
+    quickjs::{JSValue, JSValueRef},
 };
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use crate::APIConfig;
 use crate::JSApiSet;
 
 pub use config::FSConfig;
+pub use mounts::MountMode;
+pub use quota::{WriteLimits, WriteUsage};
 pub mod config;
+mod embedded;
+mod encoding;
+mod error;
+mod handles;
+mod mounts;
+mod path;
+mod quota;
+mod scratch;
+mod stats;
+
+use embedded::Entry;
+use encoding::Encoding;
+use error::{FsError, IoResultExt};
+use handles::FileTable;
+use scratch::ScratchUsage;
 
 pub(super) struct FS;
 
-#[derive(Debug)]
-pub enum FileFlag{
-    Append, // a
-    AppendCreateIfNotExist, // a+
-    AppendButFailIfExist, // ax
-    Write, // w
-    WriteButFailIfExist, // wx
-    ReadWrite, // w+
-    ReadWriteButFailIfExist, // wx+
-    Read,
+/// How a file is opened, parsed from Node's string flags (`'r'`, `'a+'`,
+/// `'wx'`, ...) or from a number combining the `O_*` constants of
+/// `Node.FS.constants`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileFlag {
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    exclusive: bool,
+    truncate: bool,
+    sync: bool,
 }
 
-// According to documentation
-/*
-
-    'a': Open file for appending. The file is created if it does not exist.
-    'a+': Open file for reading and appending. The file is created if it does not exist.
-    'as': Open file for appending in synchronous mode. The file is created if it does not exist.
-    'w': Open file for writing. The file is created (if it does not exist) or truncated (if it exists).
-    'wx': Like 'w' but fails if the path exists.
-    'w+': Open file for reading and writing. The file is created (if it does not exist) or truncated (if it exists).
-    'wx+': Like 'w+' but fails if the path exists.
-
-*/
-
-pub fn get_mut_file(filename: String, flag: FileFlag) -> Result<std::fs::File> {
-    let fd = match flag {
-        FileFlag::Read => std::fs::OpenOptions::new().read(true).open(filename)?,
-        FileFlag::Append => std::fs::OpenOptions::new().append(true).open(filename)?,
-        FileFlag::AppendCreateIfNotExist => std::fs::OpenOptions::new().append(true).create(true).open(filename)?,
-        FileFlag::AppendButFailIfExist => std::fs::OpenOptions::new().append(true).create_new(true).open(filename)?,
-        FileFlag::Write => std::fs::OpenOptions::new().write(true).create(true).open(filename)?,
-        FileFlag::WriteButFailIfExist => std::fs::OpenOptions::new().write(true).create_new(true).open(filename)?,
-        FileFlag::ReadWrite => std::fs::OpenOptions::new().read(true).write(true).open(filename)?,
-        FileFlag::ReadWriteButFailIfExist => std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(filename)?,
-    };
-    Ok(fd)
+// The values of Linux, which `Node.FS.constants` exposes too
+pub const O_RDONLY: i32 = 0;
+pub const O_WRONLY: i32 = 0o1;
+pub const O_RDWR: i32 = 0o2;
+pub const O_CREAT: i32 = 0o100;
+pub const O_EXCL: i32 = 0o200;
+pub const O_TRUNC: i32 = 0o1000;
+pub const O_APPEND: i32 = 0o2000;
+pub const O_DSYNC: i32 = 0o10000;
+pub const O_SYNC: i32 = 0o4010000;
+const O_ACCMODE: i32 = 0o3;
+
+pub fn get_mut_file(filename: &Path, flag: FileFlag) -> std::io::Result<std::fs::File> {
+    flag.open_options().open(filename)
+}
+
+impl FileFlag {
+    /// Parses one of the flags documented by Node.
+    pub fn parse(flag: &str) -> Result<Self> {
+        let read_only = Self { read: true, ..Self::default() };
+        let read_write = Self { read: true, write: true, ..Self::default() };
+        let write = Self { write: true, create: true, truncate: true, ..Self::default() };
+        let append = Self { write: true, append: true, create: true, ..Self::default() };
+        let flag = match flag {
+            "r" => read_only,
+            "rs" | "sr" => Self { sync: true, ..read_only },
+            "r+" => read_write,
+            "rs+" | "sr+" => Self { sync: true, ..read_write },
+            "w" => write,
+            "wx" | "xw" => Self { exclusive: true, ..write },
+            "w+" => Self { read: true, ..write },
+            "wx+" | "xw+" => Self { read: true, exclusive: true, ..write },
+            "a" => append,
+            "ax" | "xa" => Self { exclusive: true, ..append },
+            "as" | "sa" => Self { sync: true, ..append },
+            "a+" => Self { read: true, ..append },
+            "ax+" | "xa+" => Self { read: true, exclusive: true, ..append },
+            "as+" | "sa+" => Self { read: true, sync: true, ..append },
+            _ => anyhow::bail!("Invalid flag {}", flag),
+        };
+        Ok(flag)
+    }
+
+    /// Builds the flag from a combination of the `O_*` constants.
+    pub fn from_bits(bits: i32) -> Result<Self> {
+        let (read, write) = match bits & O_ACCMODE {
+            O_RDONLY => (true, false),
+            O_WRONLY => (false, true),
+            O_RDWR => (true, true),
+            _ => anyhow::bail!("Invalid flag {}", bits),
+        };
+        Ok(Self {
+            read,
+            write,
+            append: bits & O_APPEND != 0,
+            create: bits & O_CREAT != 0,
+            exclusive: bits & O_EXCL != 0,
+            truncate: bits & O_TRUNC != 0,
+            sync: bits & (O_SYNC | O_DSYNC) != 0,
+        })
+    }
+
+    fn open_options(&self) -> std::fs::OpenOptions {
+        let mut options = std::fs::OpenOptions::new();
+        options
+            .read(self.read)
+            // `append` implies writing for `OpenOptions`
+            .write(self.write && !self.append)
+            .append(self.append)
+            .truncate(self.truncates());
+        if self.create && self.exclusive {
+            options.create_new(true);
+        } else {
+            options.create(self.create);
+        }
+        options
+    }
+
+    /// Whether the flag opens the file for reading.
+    pub fn requires_read(&self) -> bool {
+        self.read
+    }
+
+    /// Whether the flag opens the file for writing.
+    pub fn requires_write(&self) -> bool {
+        self.write || self.append
+    }
+
+    /// Whether the flag creates the file when it does not exist.
+    pub fn creates(&self) -> bool {
+        self.create
+    }
+
+    /// Whether opening an existing file empties it.
+    pub fn truncates(&self) -> bool {
+        self.truncate && self.write && !self.append
+    }
+
+    /// Whether writes go to the end of the file.
+    pub fn appends(&self) -> bool {
+        self.append
+    }
+
+    /// Whether writes must reach the storage before returning.
+    pub fn syncs(&self) -> bool {
+        self.sync
+    }
 }
 
 impl TryFrom<String> for FileFlag {
     type Error = anyhow::Error;
     fn try_from(value: String) -> Result<Self> {
-        match value.as_str() {
-            "r" => Ok(FileFlag::Read),
-            "a" => Ok(FileFlag::Append),
-            "a+" => Ok(FileFlag::AppendCreateIfNotExist),
-            "ax" => Ok(FileFlag::AppendButFailIfExist),
-            "ax+" => Ok(FileFlag::AppendButFailIfExist),
-            "w" => Ok(FileFlag::Write),
-            "wx" => Ok(FileFlag::WriteButFailIfExist),
-            "w+" => Ok(FileFlag::ReadWrite),
-            "wx+" => Ok(FileFlag::ReadWriteButFailIfExist),
-            _ => anyhow::bail!("Invalid flag {}", value)
+        Self::parse(&value)
+    }
+}
+
+impl TryFrom<&JSValueRef<'_>> for FileFlag {
+    type Error = anyhow::Error;
+    fn try_from(value: &JSValueRef) -> Result<Self> {
+        if value.is_number() {
+            Self::from_bits(value.try_as_integer()?)
+        } else {
+            Self::parse(value.as_str()?)
+        }
+    }
+}
+
+
+/// Resolves `path` for reading. Denials throw an `EACCES` error naming the
+/// canonical path that was evaluated, unless the config is quiet, then only a
+/// warning is printed and `None` is returned.
+fn check_read(config: &FSConfig, syscall: &str, path: &str) -> Result<Option<PathBuf>> {
+    check_access(config, config.resolve_read(path), "read", syscall)
+}
+
+/// Resolves `path` for writing, see [`check_read`]. Embedded files and
+/// read-only mounts can't be written whatever the permissions.
+fn check_write(config: &FSConfig, syscall: &str, path: &str) -> Result<Option<PathBuf>> {
+    if config.embedded().is_read_only(path) || config.is_read_only_mount(path) {
+        return Err(FsError::new("EROFS", syscall, Some(path)).into());
+    }
+    check_access(config, config.resolve_write(path), "write", syscall)
+}
+
+/// Opens `path`, counting the file against the limit of created files when
+/// the flag creates it and the open succeeds.
+fn open_file(config: &FSConfig, path: &str, resolved: &Path, flag: &FileFlag) -> Result<std::fs::File> {
+    let existing = std::fs::symlink_metadata(resolved).ok();
+    let creates = flag.creates() && existing.is_none();
+    if creates && !config.quota().can_create(1) {
+        return Err(FsError::quota_exceeded("open", Some(path)).into());
+    }
+    let file = get_mut_file(resolved, *flag).fs_err("open", path)?;
+    if creates {
+        config.quota().count_created(1);
+    }
+    if let (Some(metadata), Some((usage, _))) = (existing, config.scratch_limit(resolved)) {
+        if flag.truncates() {
+            usage.shrink(metadata.len());
         }
     }
+    Ok(file)
+}
+
+/// The scratch usage to release, and by how much, once the file or
+/// directory at `resolved` is removed or emptied.
+fn scratch_release<'a>(config: &'a FSConfig, resolved: &Path) -> Option<(&'a ScratchUsage, u64)> {
+    let (usage, _) = config.scratch_limit(resolved)?;
+    Some((usage, scratch::size(resolved).unwrap_or(0)))
+}
+
+/// Checks the write limits before writing `len` bytes at the current position
+/// of `file`, or at its end when it appends. `resolved` is where the file
+/// lives, to check the size of the scratch area.
+fn reserve_write(
+    config: &FSConfig,
+    file: &mut std::fs::File,
+    resolved: &Path,
+    append: bool,
+    len: u64,
+    path: Option<&str>,
+) -> Result<()> {
+    let quota = config.quota();
+    let scratch = config.scratch_limit(resolved);
+    if !quota.is_limited() && scratch.is_none() {
+        return Ok(());
+    }
+    let size = file.metadata().map_err(|e| FsError::from_io(&e, "write", path))?.len();
+    let position = if append {
+        size
+    } else {
+        file.stream_position().map_err(|e| FsError::from_io(&e, "write", path))?
+    };
+    let new_size = size.max(position + len);
+
+    if let Some((usage, max_bytes)) = scratch {
+        if usage.get() + (new_size - size) > max_bytes {
+            return Err(FsError::new("ENOSPC", "write", path).into());
+        }
+    }
+    if !quota.reserve_write(len, new_size) {
+        return Err(FsError::quota_exceeded("write", path).into());
+    }
+    if let Some((usage, _)) = scratch {
+        usage.grow(new_size - size);
+    }
+    Ok(())
+}
+
+/// Writes `data` at `offset`, returns the number of bytes written or `0` if a
+/// quiet config denied the write.
+fn write_file(config: &FSConfig, filename: &str, data: &[u8], offset: u64, flag: &FileFlag) -> Result<usize> {
+    let Some(resolved) = check_write(config, "open", filename)? else {
+        return Ok(0);
+    };
+
+    let mut fd = open_file(config, filename, &resolved, flag)?;
+    fd.seek(std::io::SeekFrom::Start(offset)).fs_err("write", filename)?;
+    reserve_write(config, &mut fd, &resolved, flag.appends(), data.len() as u64, Some(filename))?;
+
+    let n = fd.write(data).fs_err("write", filename)?;
+    fd.flush().fs_err("write", filename)?;
+    if flag.syncs() {
+        fd.sync_data().fs_err("write", filename)?;
+    }
+    Ok(n)
+}
+
+/// Writes `data` to an open file at `position`, or at its current position
+/// when `position` is negative.
+fn write_fd(config: &FSConfig, files: &mut FileTable, fd: i32, data: &[u8], position: f64) -> Result<usize> {
+    let (flag, resolved) = files.info(fd).ok_or_else(|| FsError::bad_fd("write"))?;
+    let file = files.get_mut(fd).ok_or_else(|| FsError::bad_fd("write"))?;
+    if position >= 0.0 {
+        file.seek(std::io::SeekFrom::Start(position as u64))
+            .map_err(|e| FsError::from_io(&e, "write", None))?;
+    }
+
+    reserve_write(config, file, &resolved, flag.appends(), data.len() as u64, None)?;
+    let n = file.write(data).map_err(|e| FsError::from_io(&e, "write", None))?;
+    file.flush().map_err(|e| FsError::from_io(&e, "write", None))?;
+    if flag.syncs() {
+        file.sync_data().map_err(|e| FsError::from_io(&e, "write", None))?;
+    }
+    Ok(n)
+}
+
+/// Reads the whole file, or returns `None` if a quiet config denied it.
+fn read_file(config: &FSConfig, filename: &str, flag: &FileFlag) -> Result<Option<Vec<u8>>> {
+    // Embedded files are served from memory and always readable
+    match config.embedded().lookup(filename) {
+        Some(Entry::File(contents)) => return Ok(Some(contents.to_vec())),
+        Some(Entry::Directory) => return Err(FsError::new("EISDIR", "read", Some(filename)).into()),
+        None => {}
+    }
+
+    let Some(resolved) = check_read(config, "open", filename)? else {
+        return Ok(None);
+    };
+    let mut fd = open_file(config, filename, &resolved, flag)?;
+    let mut contents = vec![];
+    fd.read_to_end(&mut contents).fs_err("read", filename)?;
+    Ok(Some(contents))
 }
 
+fn check_access(
+    config: &FSConfig,
+    resolved: std::result::Result<PathBuf, PathBuf>,
+    access: &str,
+    syscall: &str,
+) -> Result<Option<PathBuf>> {
+    let canonical = match resolved {
+        Ok(path) => return Ok(Some(path)),
+        Err(canonical) => canonical.to_string_lossy().to_string(),
+    };
+    if config.is_quiet() {
+        // Just emit a message, be quiet
+        eprintln!("[WASM] warning {} has no {} permissions", canonical, access);
+        Ok(None)
+    } else {
+        Err(FsError::access_denied(syscall, &canonical).into())
+    }
+}
 
 impl JSApiSet for FS {
     fn register(&self, runtime: &Runtime, config: &APIConfig) -> Result<()> {
@@ -100,138 +360,517 @@ impl JSApiSet for FS {
                 };
 
                 let filename: String = filename.try_into()?;
-                eprintln!("writing to {}", filename);
-                if configcp.can_write(&filename){
+                let flag = FileFlag::try_from(flag)?;
+                let offset: i32 = offset.try_into()?;
+                let datalength: usize = datalength.try_into()?;
+                let dataoffset: usize = dataoffset.try_into()?;
 
-                    let flag: String = flag.try_into()?;
-                    let flag: FileFlag = flag.try_into()?;
-                    let offset: i32 = offset.try_into()?;
-                    let datalength: usize = datalength.try_into()?;
-                    let dataoffset: usize = dataoffset.try_into()?;
+                let data = data.as_bytes()?;
+                let data = &data[dataoffset..(dataoffset + datalength)];
+                let n = write_file(&configcp, &filename, data, offset as u64, &flag)?;
+                Ok(n.into())
+            })?,
+        )?;
 
-                    // match of options is string or object
-                    // Get the file
-                    // Create if append is false
-                    let mut fd = get_mut_file(filename, flag)?;
-                    // TODO set the writting encoding
-                    // Set the offset to write
-                    fd.seek(std::io::SeekFrom::Start(offset as u64));
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__writeFileStringSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [filename, data, encoding, offset, flag, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let filename: String = filename.try_into()?;
+                let data: String = data.try_into()?;
+                let encoding: String = encoding.try_into()?;
+                let encoding = Encoding::try_from(encoding.as_str())?;
+                let offset: i32 = offset.try_into()?;
+                let flag = FileFlag::try_from(flag)?;
 
-                    let data = data.as_bytes_mut()?;
-                    let data = &mut data[dataoffset..(dataoffset + datalength)];
+                let data = encoding.encode(&data);
+                let n = write_file(&configcp, &filename, &data, offset as u64, &flag)?;
+                Ok(n.into())
+            })?,
+        )?;
 
-                    let n = fd.write(&data)?;
-                    // let n = fd.write(&data)?;
-                    fd.flush()?;
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__readFileSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [filename, data, _dataoffset, _datalength, offset, flag, _encoding, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
 
-                    Ok(n.into())
+                let filename: String = filename.try_into()?;
+                let data = data.as_bytes_mut()?;
+                let offset: i32 = offset.try_into()?;
 
-                } else {
-                    // Just emit a message, be quiet
-                    eprintln!("[WASM] warning {} has no write permissions", filename);
-                    Ok(0.into())
+                // Embedded files are served from memory and always readable
+                match configcp.embedded().lookup(&filename) {
+                    Some(Entry::File(contents)) => {
+                        let contents = contents.get(offset as usize..).unwrap_or_default();
+                        let n = contents.len().min(data.len());
+                        data[..n].copy_from_slice(&contents[..n]);
+                        return Ok(n.into());
+                    }
+                    Some(Entry::Directory) => {
+                        return Err(FsError::new("EISDIR", "read", Some(&filename)).into());
+                    }
+                    None => {}
                 }
-                
+
+                let Some(resolved) = check_read(&configcp, "open", &filename)? else {
+                    return Ok(0.into());
+                };
+
+                let flag = FileFlag::try_from(flag)?;
+                // match of options is string or object
+                // Get the file
+                // Create if append is false
+
+                let mut fd = open_file(&configcp, &filename, &resolved, &flag)?;
+                fd.seek(std::io::SeekFrom::Start(offset as u64)).fs_err("read", &filename)?;
+
+                let n = fd.read(data).fs_err("read", &filename)?;
+
+                Ok(n.into())
             })?,
         )?;
 
         let configcp = config.fs.clone();
         global.set_property(
-            "__readFileSync",
+            "__readFileStringSync",
             context.wrap_callback(move |_, _this_arg, args| {
-                let [filename, data, dataoffset, datalength, offset, flag, encoding, ..] = args else {
+                let [filename, flag, encoding, ..] = args else {
                     anyhow::bail!("Invalid number of parameters");
                 };
 
                 let filename: String = filename.try_into()?;
-                if configcp.can_read(&filename){
-                    let mut data = data.as_bytes_mut()?;
-                    let offset: i32 = offset.try_into()?;
-                    let flag: String = flag.try_into()?;
-                    let flag: FileFlag = flag.try_into()?;
-                    let encoding: String = encoding.try_into()?;
-                    // match of options is string or object
-                    // Get the file
-                    // Create if append is false
-                    
-                    let mut fd = get_mut_file(filename, flag)?;
-                    fd.seek(std::io::SeekFrom::Start(offset as u64))?;
-
-                    let n = fd.read(&mut data)?;
-
-                    Ok(n.into())
+                let flag = FileFlag::try_from(flag)?;
+                let encoding: String = encoding.try_into()?;
+                let encoding = Encoding::try_from(encoding.as_str())?;
 
-                } else {
-                    // Just emit a message, be quiet
-                    eprintln!("[WASM] warning {} has no read permissions", filename);
-                    Ok(0.into())
-                }
+                let contents = read_file(&configcp, &filename, &flag)?.unwrap_or_default();
+                Ok(encoding.decode(&contents).into())
             })?,
         )?;
 
         let configcp = config.fs.clone();
         global.set_property(
             "__statSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [filename, follow_symlinks, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let filename: String = filename.try_into()?;
+                let follow_symlinks: bool = follow_symlinks.try_into()?;
+                let syscall = if follow_symlinks { "stat" } else { "lstat" };
+                if let Some(entry) = configcp.embedded().lookup(&filename) {
+                    return Ok(stats::embedded_to_js(&entry));
+                }
+                let Some(resolved) = check_read(&configcp, syscall, &filename)? else {
+                    let result  = HashMap::from([("size", -1)]);
+                    return Ok(JSValue::from_hashmap(result));
+                };
+
+                // Only look at the metadata, so directories can be stat'ed too
+                let metadata = if follow_symlinks {
+                    std::fs::metadata(&resolved)
+                } else {
+                    std::fs::symlink_metadata(&resolved)
+                };
+                let metadata = metadata.fs_err(syscall, &filename)?;
+                Ok(stats::metadata_to_js(&metadata))
+            })?,
+        )?;
+
+
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__openwrite",
             context.wrap_callback(move |_, _this_arg, args| {
                 let [filename, flag, ..] = args else {
                     anyhow::bail!("Invalid number of parameters");
                 };
 
                 let filename: String = filename.try_into()?;
+                let Some(resolved) = check_write(&configcp, "open", &filename)? else {
+                    return Ok(0.into());
+                };
 
-                if configcp.can_read(&filename){
-                    let flag: String = flag.try_into()?;
-                    let flag: FileFlag = flag.try_into()?;
-                    // match of options is string or object
-                    // Get the file
-                    // Create if append is false
-                    let mut fd = get_mut_file(filename, flag)?;
-                    // Get the size of the file
-                    let metadata = fd.metadata()?;
-
-                    let size: JSValue = (metadata.len() as i32).try_into()?;
-                    let ino: JSValue = (0i32).try_into()?;
-                    let result  = HashMap::from([("size", size), ("ino", ino)]);
-                    // TODO add the others as needed
-                    // result.insert("size", size);
-                    Ok(JSValue::from_hashmap(result))
-                } else {                   
-                    let result  = HashMap::from([("size", -1)]);
-                    // Just emit a message, be quiet
-                    eprintln!("[WASM] warning {} has no read permissions", filename);
-                    Ok(JSValue::from_hashmap(result))
+                let flag = FileFlag::try_from(flag)?;
+                // Emptied below when the flag does not do it already
+                let emptied = (!flag.truncates())
+                    .then(|| scratch_release(&configcp, &resolved))
+                    .flatten();
+                // match of options is string or object
+                // Get the file
+                // Create if append is false
+                let fd = open_file(&configcp, &filename, &resolved, &flag)?;
+                // truncate
+                fd.set_len(0).fs_err("ftruncate", &filename)?;
+                if let Some((usage, size)) = emptied {
+                    usage.shrink(size);
+                }
+                Ok(1.into())
+            })?,
+        )?;
+
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__readdirSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [path, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let path: String = path.try_into()?;
+                if let Some(names) = configcp.embedded().read_dir(&path) {
+                    return Ok(JSValue::from_vec(names));
+                }
+                if let Some(Entry::File(_)) = configcp.embedded().lookup(&path) {
+                    return Err(FsError::new("ENOTDIR", "scandir", Some(&path)).into());
+                }
+                let Some(resolved) = check_read(&configcp, "scandir", &path)? else {
+                    return Ok(JSValue::from_vec(Vec::<String>::new()));
+                };
+
+                let mut names = vec![];
+                for entry in std::fs::read_dir(&resolved).fs_err("scandir", &path)? {
+                    let name = entry.fs_err("scandir", &path)?.file_name();
+                    names.push(name.to_string_lossy().to_string());
+                }
+                // The order of `read_dir` depends on the host, keep it stable
+                names.sort();
+                Ok(JSValue::from_vec(names))
+            })?,
+        )?;
+
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__mkdirSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [path, recursive, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let path: String = path.try_into()?;
+                let recursive: bool = recursive.try_into()?;
+                let Some(resolved) = check_write(&configcp, "mkdir", &path)? else {
+                    return Ok(JSValue::Undefined);
+                };
+
+                if recursive {
+                    let missing: Vec<&Path> = resolved
+                        .ancestors()
+                        .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+                        .collect();
+                    if !configcp.quota().can_create(missing.len() as u64) {
+                        return Err(FsError::quota_exceeded("mkdir", Some(&path)).into());
+                    }
+                    // Like Node, return the first directory that had to be created
+                    let first_created = missing
+                        .last()
+                        .map(|p| configcp.virtual_path(p).to_string_lossy().to_string());
+                    std::fs::create_dir_all(&resolved).fs_err("mkdir", &path)?;
+                    configcp.quota().count_created(missing.len() as u64);
+                    Ok(first_created.map_or(JSValue::Undefined, |p| p.into()))
+                } else {
+                    if !configcp.quota().can_create(1) {
+                        return Err(FsError::quota_exceeded("mkdir", Some(&path)).into());
+                    }
+                    std::fs::create_dir(&resolved).fs_err("mkdir", &path)?;
+                    configcp.quota().count_created(1);
+                    Ok(JSValue::Undefined)
                 }
             })?,
         )?;
 
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__tmpdir",
+            context.wrap_callback(move |_, _this_arg, _args| {
+                // Like Node on Linux when there is no scratch area
+                let dir = configcp.scratch_path().unwrap_or_else(|| PathBuf::from("/tmp"));
+                Ok(dir.to_string_lossy().to_string().into())
+            })?,
+        )?;
 
         let configcp = config.fs.clone();
         global.set_property(
-            "__openwrite",
+            "__mkdtempSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [prefix, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let prefix: String = prefix.try_into()?;
+                if !configcp.quota().can_create(1) {
+                    return Err(FsError::quota_exceeded("mkdtemp", Some(&prefix)).into());
+                }
+                // Retry in the unlikely case the name is taken
+                for _ in 0..16 {
+                    let path = format!("{}{}", prefix, scratch::random_suffix());
+                    let Some(resolved) = check_write(&configcp, "mkdtemp", &path)? else {
+                        return Ok(JSValue::Undefined);
+                    };
+                    match std::fs::create_dir(&resolved) {
+                        Ok(()) => {
+                            configcp.quota().count_created(1);
+                            return Ok(path.into());
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                        Err(e) => return Err(FsError::from_io(&e, "mkdtemp", Some(&path)).into()),
+                    }
+                }
+                Err(FsError::new("EEXIST", "mkdtemp", Some(&prefix)).into())
+            })?,
+        )?;
+
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__rmSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [path, recursive, force, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let path: String = path.try_into()?;
+                let recursive: bool = recursive.try_into()?;
+                let force: bool = force.try_into()?;
+                let Some(resolved) = check_write(&configcp, "rm", &path)? else {
+                    return Ok(JSValue::Undefined);
+                };
+
+                let metadata = match std::fs::symlink_metadata(&resolved) {
+                    Ok(metadata) => metadata,
+                    // `force` ignores paths that do not exist
+                    Err(e) if force && e.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok(JSValue::Undefined)
+                    }
+                    Err(e) => return Err(FsError::from_io(&e, "rm", Some(&path)).into()),
+                };
+
+                let released = scratch_release(&configcp, &resolved);
+                if metadata.is_dir() {
+                    if !recursive {
+                        return Err(FsError::new("EISDIR", "rm", Some(&path)).into());
+                    }
+                    std::fs::remove_dir_all(&resolved).fs_err("rm", &path)?;
+                } else {
+                    std::fs::remove_file(&resolved).fs_err("rm", &path)?;
+                }
+                if let Some((usage, size)) = released {
+                    usage.shrink(size);
+                }
+                Ok(JSValue::Undefined)
+            })?,
+        )?;
+
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__unlinkSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [path, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let path: String = path.try_into()?;
+                let Some(resolved) = check_write(&configcp, "unlink", &path)? else {
+                    return Ok(JSValue::Undefined);
+                };
+
+                let released = scratch_release(&configcp, &resolved);
+                std::fs::remove_file(&resolved).fs_err("unlink", &path)?;
+                if let Some((usage, size)) = released {
+                    usage.shrink(size);
+                }
+                Ok(JSValue::Undefined)
+            })?,
+        )?;
+
+        let configcp = config.fs.clone();
+        global.set_property(
+            "__renameSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [old_path, new_path, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let old_path: String = old_path.try_into()?;
+                let new_path: String = new_path.try_into()?;
+
+                // Renaming removes the old entry and creates the new one
+                let Some(resolved_old) = check_write(&configcp, "rename", &old_path)? else {
+                    return Ok(JSValue::Undefined);
+                };
+                let Some(resolved_new) = check_write(&configcp, "rename", &new_path)? else {
+                    return Ok(JSValue::Undefined);
+                };
+
+                // Moving files in or out of the scratch area changes its usage
+                let old_scratch = configcp.scratch_limit(&resolved_old);
+                let new_scratch = configcp.scratch_limit(&resolved_new);
+                let (size, replaced) = if old_scratch.is_some() || new_scratch.is_some() {
+                    (scratch::size(&resolved_old).unwrap_or(0), scratch::size(&resolved_new).unwrap_or(0))
+                } else {
+                    (0, 0)
+                };
+                if let (None, Some((usage, max_bytes))) = (old_scratch, new_scratch) {
+                    if usage.get() - replaced.min(usage.get()) + size > max_bytes {
+                        return Err(FsError::new("ENOSPC", "rename", Some(&new_path)).into());
+                    }
+                }
+
+                std::fs::rename(&resolved_old, &resolved_new).fs_err("rename", &old_path)?;
+                match (old_scratch, new_scratch) {
+                    (_, Some((usage, _))) => {
+                        usage.shrink(replaced);
+                        if old_scratch.is_none() {
+                            usage.grow(size);
+                        }
+                    }
+                    (Some((usage, _)), None) => usage.shrink(size),
+                    (None, None) => {}
+                }
+                Ok(JSValue::Undefined)
+            })?,
+        )?;
+
+        let files = Rc::new(RefCell::new(FileTable::default()));
+        let files_cp = files.clone();
+        runtime.on_drop(move || files_cp.borrow_mut().close_all());
+
+        let configcp = config.fs.clone();
+        let files_cp = files.clone();
+        global.set_property(
+            "__openSync",
             context.wrap_callback(move |_, _this_arg, args| {
                 let [filename, flag, ..] = args else {
                     anyhow::bail!("Invalid number of parameters");
                 };
 
                 let filename: String = filename.try_into()?;
+                let flag = FileFlag::try_from(flag)?;
 
-                if configcp.can_write(&filename){
-                    let flag: String = flag.try_into()?;
-                    let flag: FileFlag = flag.try_into()?;
-                    // match of options is string or object
-                    // Get the file
-                    // Create if append is false
-                    let mut fd = get_mut_file(filename, flag)?;
-                    // truncate
-                    fd.set_len(0)?;
-                    // result.insert("size", size);
-                    Ok(1.into())
-                } else {                   
-                    let result  = HashMap::from([("size", -1)]);
-                    // Just emit a message, be quiet
-                    eprintln!("[WASM] warning {} has no read permissions", filename);
-                    Ok(0.into())
+                // Permissions are only checked here, the fd is the capability afterwards
+                let resolved = match (flag.requires_read(), flag.requires_write()) {
+                    (true, true) => check_read(&configcp, "open", &filename)?
+                        .and(check_write(&configcp, "open", &filename)?),
+                    (_, true) => check_write(&configcp, "open", &filename)?,
+                    _ => check_read(&configcp, "open", &filename)?,
+                };
+                let Some(resolved) = resolved else {
+                    return Ok((-1).into());
+                };
+
+                let fd = open_file(&configcp, &filename, &resolved, &flag)?;
+                Ok(files_cp.borrow_mut().insert(fd, flag, resolved).into())
+            })?,
+        )?;
+
+        let files_cp = files.clone();
+        global.set_property(
+            "__readSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [fd, data, dataoffset, datalength, position, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let fd: i32 = fd.try_into()?;
+                let dataoffset: usize = dataoffset.try_into()?;
+                let datalength: usize = datalength.try_into()?;
+                let position = position.as_f64()?;
+
+                let mut files = files_cp.borrow_mut();
+                let file = files.get_mut(fd).ok_or_else(|| FsError::bad_fd("read"))?;
+                // A negative position reads from the current position of the file
+                if position >= 0.0 {
+                    file.seek(std::io::SeekFrom::Start(position as u64))
+                        .map_err(|e| FsError::from_io(&e, "read", None))?;
+                }
+
+                let data = data.as_bytes_mut()?;
+                let data = &mut data[dataoffset..(dataoffset + datalength)];
+                let n = file.read(data).map_err(|e| FsError::from_io(&e, "read", None))?;
+                Ok(n.into())
+            })?,
+        )?;
+
+        let configcp = config.fs.clone();
+        let files_cp = files.clone();
+        global.set_property(
+            "__writeSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [fd, data, dataoffset, datalength, position, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let fd: i32 = fd.try_into()?;
+                let dataoffset: usize = dataoffset.try_into()?;
+                let datalength: usize = datalength.try_into()?;
+                let position = position.as_f64()?;
+
+                let data = data.as_bytes()?;
+                let data = &data[dataoffset..(dataoffset + datalength)];
+                let n = write_fd(&configcp, &mut files_cp.borrow_mut(), fd, data, position)?;
+                Ok(n.into())
+            })?,
+        )?;
+
+        let configcp = config.fs.clone();
+        let files_cp = files.clone();
+        global.set_property(
+            "__writeStringSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [fd, data, encoding, position, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let fd: i32 = fd.try_into()?;
+                let data: String = data.try_into()?;
+                let encoding: String = encoding.try_into()?;
+                let encoding = Encoding::try_from(encoding.as_str())?;
+                let position = position.as_f64()?;
+
+                let data = encoding.encode(&data);
+                let n = write_fd(&configcp, &mut files_cp.borrow_mut(), fd, &data, position)?;
+                Ok(n.into())
+            })?,
+        )?;
+
+        let files_cp = files.clone();
+        global.set_property(
+            "__fstatSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [fd, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let fd: i32 = fd.try_into()?;
+                let mut files = files_cp.borrow_mut();
+                let file = files.get_mut(fd).ok_or_else(|| FsError::bad_fd("fstat"))?;
+                let metadata = file
+                    .metadata()
+                    .map_err(|e| FsError::from_io(&e, "fstat", None))?;
+                Ok(stats::metadata_to_js(&metadata))
+            })?,
+        )?;
+
+        let files_cp = files.clone();
+        global.set_property(
+            "__closeSync",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [fd, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+
+                let fd: i32 = fd.try_into()?;
+                if !files_cp.borrow_mut().close(fd) {
+                    return Err(FsError::bad_fd("close").into());
                 }
+                Ok(JSValue::Undefined)
             })?,
         )?;
 
@@ -244,7 +883,7 @@ impl JSApiSet for FS {
 
 #[cfg(test)]
 mod tests {
-    use crate::{fs::FS, APIConfig, JSApiSet};
+    use crate::{fs::{MountMode, WriteLimits, FS}, APIConfig, JSApiSet};
     use anyhow::Result;
     use javy::Runtime;
 
@@ -271,6 +910,21 @@ mod tests {
         config.fs.add_to_write_whitelist("testa.*");
         FS.register(&runtime, &config)?;
         let ctx = runtime.context();
+        ctx.eval_global("test.js", "try { Node.FS.writeFileSync('test.txt', new Uint8Array([83])) } catch (e) { result = [e.code, e.syscall, e.path].join(',') }")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "EACCES,open,test.txt");
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_fs_write_forbidden_quiet() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_write_whitelist("testa.*");
+        config.fs.set_quiet(true);
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
         ctx.eval_global("test.js", "result = Node.FS.writeFileSync('test.txt', new Uint8Array([83]))")?;
         let result = ctx.global_object()?.get_property("result")?.try_as_integer()?;
         assert_eq!(result, 0);
@@ -278,6 +932,33 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_fs_error_codes() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./*");
+        config.fs.add_to_write_whitelist("./*");
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", "try { Node.FS.statSync('./missing.txt', 'r') } catch (e) { result = e.code }")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "ENOENT");
+
+        ctx.eval_global("test.js", "try { Node.FS.mkdirSync('./src') } catch (e) { result = e.code }")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "EEXIST");
+
+        ctx.eval_global("test.js", "try { Node.FS.rmSync('./src') } catch (e) { result = e.code }")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "EISDIR");
+
+        ctx.eval_global("test.js", "try { Node.FS.closeSync(42) } catch (e) { result = e.code }")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "EBADF");
+        Ok(())
+    }
+
+
     #[test]
     fn test_fs_write_ok_to_root() -> Result<()> {
         let runtime = Runtime::default();
@@ -303,9 +984,8 @@ mod tests {
 
         FS.register(&runtime, &config)?;
         let ctx = runtime.context();
-        ctx.eval_global("test.js", "result = Node.FS.writeFileSync('./.test', new Uint8Array([83]), 'w')")?;
-        let result = ctx.global_object()?.get_property("result")?.try_as_integer()?;
-        assert_eq!(result, 0);
+        let denied = ctx.eval_global("test.js", "result = Node.FS.writeFileSync('./.test', new Uint8Array([83]), 'w')");
+        assert!(denied.is_err());
 
         ctx.eval_global("test.js", "result = Node.FS.writeFileSync('./test.txt', new Uint8Array([83]), 'w')")?;
         let result = ctx.global_object()?.get_property("result")?.try_as_integer()?;
@@ -334,4 +1014,537 @@ mod tests {
         assert!(result > 2);
         Ok(())
     }
+
+
+    #[test]
+    fn test_fs_directory_operations() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_write_whitelist("./test_dir_ops*");
+        config.fs.add_to_read_whitelist("./test_dir_ops*");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", "result = Node.FS.mkdirSync('./test_dir_ops/a/b', { recursive: true })")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "./test_dir_ops");
+
+        ctx.eval_global("test.js", "Node.FS.writeFileSync('./test_dir_ops/a/file.txt', new Uint8Array([83]), 0, 'w')")?;
+        ctx.eval_global("test.js", "Node.FS.renameSync('./test_dir_ops/a/file.txt', './test_dir_ops/a/moved.txt')")?;
+        ctx.eval_global("test.js", "result = Node.FS.readdirSync('./test_dir_ops/a').join(',')")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "b,moved.txt");
+
+        ctx.eval_global("test.js", "Node.FS.unlinkSync('./test_dir_ops/a/moved.txt')")?;
+        ctx.eval_global("test.js", "result = Node.FS.readdirSync('./test_dir_ops/a').join(',')")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "b");
+
+        ctx.eval_global("test.js", "Node.FS.rmSync('./test_dir_ops', { recursive: true })")?;
+        assert!(!std::path::Path::new("./test_dir_ops").exists());
+
+        // Removing a missing path is fine with `force`
+        ctx.eval_global("test.js", "Node.FS.rmSync('./test_dir_ops', { force: true })")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_directory_operations_forbidden() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist(".");
+        config.fs.add_to_read_whitelist("./*");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        // Listing is read access
+        ctx.eval_global("test.js", "result = Node.FS.readdirSync('.').length > 0")?;
+        assert!(ctx.global_object()?.get_property("result")?.as_bool()?);
+
+        // Creating a directory is write access
+        assert!(ctx.eval_global("test.js", "Node.FS.mkdirSync('./test_dir_forbidden')").is_err());
+        assert!(!std::path::Path::new("./test_dir_forbidden").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_file_descriptors() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_write_whitelist("./test_fd.txt");
+        config.fs.add_to_read_whitelist("./test_fd.txt");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            let fd = Node.FS.openSync('./test_fd.txt', 'w+');
+            Node.FS.writeSync(fd, new Uint8Array([1, 2, 3, 4]));
+            let buffer = new Uint8Array(2);
+            // Read the middle two bytes by position
+            let n = Node.FS.readSync(fd, buffer, 0, 2, 1);
+            let size = Node.FS.fstatSync(fd).size;
+            Node.FS.closeSync(fd);
+            result = [n, buffer[0], buffer[1], size].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "2,2,3,4");
+
+        // The fd is no longer valid once closed
+        assert!(ctx.eval_global("test.js", "Node.FS.fstatSync(fd)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_open_checks_permissions() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./test_fd_read_only.txt");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", "try { Node.FS.openSync('./test_fd_read_only.txt', 'w') } catch (e) { result = e.code }")?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "EACCES");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_stat() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./*");
+        config.fs.add_to_write_whitelist("./test_stat.txt");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            Node.FS.writeFileSync('./test_stat.txt', new Uint8Array([1, 2, 3]), 0, 'w');
+            let file = Node.FS.statSync('./test_stat.txt');
+            let dir = Node.FS.statSync('./src');
+            result = [
+                file.size,
+                file.isFile(),
+                file.isDirectory(),
+                dir.isDirectory(),
+                file.mtimeMs > 0,
+                typeof Node.FS.statSync('./test_stat.txt', { bigint: true }).size,
+                Node.FS.statSync('./missing.txt', { throwIfNoEntry: false }),
+                Node.FS.existsSync('./test_stat.txt'),
+                Node.FS.existsSync('./missing.txt'),
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "3,true,false,true,true,bigint,,true,false");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_traversal_is_denied() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./*");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            try { Node.FS.statSync('./src/../../../etc/passwd') } catch (e) { result = [e.code, e.path].join(',') }
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "EACCES,../../etc/passwd");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_symlink_escape_is_denied() -> Result<()> {
+        let link = "./test_escape_link";
+        let _ = std::fs::remove_file(link);
+        std::os::unix::fs::symlink("/etc", link)?;
+
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./*");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            try { Node.FS.readdirSync('./test_escape_link') } catch (e) { result = [e.code, e.path].join(',') }
+        "#)?;
+        std::fs::remove_file(link)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "EACCES,/etc");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_embedded_dir() -> Result<()> {
+        let host_dir = "./test_embed_dir";
+        let _ = std::fs::remove_dir_all(host_dir);
+        std::fs::create_dir_all(format!("{host_dir}/nested"))?;
+        std::fs::write(format!("{host_dir}/config.json"), "{}")?;
+        std::fs::write(format!("{host_dir}/nested/table.txt"), "abc")?;
+
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.embed_dir(std::path::Path::new(host_dir), "/assets")?;
+        // Served from memory from now on
+        std::fs::remove_dir_all(host_dir)?;
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            let write = 'none';
+            try { Node.FS.writeFileSync('/assets/new.txt', new Uint8Array([1]), 0, 'w') } catch (e) { write = e.code }
+            result = [
+                Node.FS.readFileSync('/assets/nested/table.txt').length,
+                Node.FS.statSync('/assets/config.json').size,
+                Node.FS.statSync('/assets/nested').isDirectory(),
+                Node.FS.readdirSync('/assets').join('|'),
+                write,
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "3,2,true,config.json|nested,EROFS");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_write_limits() -> Result<()> {
+        for file in ["./test_quota_a.txt", "./test_quota_b.txt", "./test_quota_c.txt"] {
+            let _ = std::fs::remove_file(file);
+        }
+        for dir in ["./test_quota_d", "./test_quota_e"] {
+            let _ = std::fs::remove_dir(dir);
+        }
+
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_write_whitelist("./test_quota_*");
+        config.fs.set_write_limits(WriteLimits {
+            max_bytes_per_invocation: Some(8),
+            max_file_size: Some(4),
+            max_files_created: Some(2),
+        });
+        let usage = config.fs.write_usage();
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        let code = |script: &str| -> Result<String> {
+            ctx.eval_global("test.js", &format!("try {{ {script}; result = 'ok' }} catch (e) {{ result = e.code }}"))?;
+            Ok(ctx.global_object()?.get_property("result")?.try_into()?)
+        };
+
+        assert_eq!(code("Node.FS.writeFileSync('./test_quota_a.txt', new Uint8Array(4), 0, 'w')")?, "ok");
+        // The file would grow to 5 bytes
+        assert_eq!(code("Node.FS.writeFileSync('./test_quota_a.txt', new Uint8Array(2), 3, 'w')")?, "EDQUOT");
+        assert_eq!(code("Node.FS.writeFileSync('./test_quota_b.txt', new Uint8Array(4), 0, 'w')")?, "ok");
+        // 8 bytes were already written
+        assert_eq!(code("Node.FS.writeFileSync('./test_quota_a.txt', new Uint8Array(1), 0, 'w')")?, "EDQUOT");
+        // Only two files can be created
+        assert_eq!(code("Node.FS.openSync('./test_quota_c.txt', 'w')")?, "EDQUOT");
+
+        usage.reset();
+        assert_eq!(code("Node.FS.writeFileSync('./test_quota_a.txt', new Uint8Array(1), 0, 'w')")?, "ok");
+        // Failed creations are not counted
+        assert_eq!(code("Node.FS.openSync('./test_quota_a.txt', 'wx')")?, "EEXIST");
+        assert_eq!(code("Node.FS.openSync('./test_quota_none/c.txt', 'w')")?, "ENOENT");
+        assert_eq!(code("Node.FS.closeSync(Node.FS.openSync('./test_quota_c.txt', 'w'))")?, "ok");
+        // Directories count too
+        assert_eq!(code("Node.FS.mkdirSync('./test_quota_d')")?, "ok");
+        assert_eq!(code("Node.FS.mkdirSync('./test_quota_e')")?, "EDQUOT");
+        std::fs::remove_dir("./test_quota_d")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_encodings() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./test_encoding_*");
+        config.fs.add_to_write_whitelist("./test_encoding_*");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            Node.FS.writeFileSync('./test_encoding_utf8.txt', 'héllo');
+            Node.FS.writeFileSync('./test_encoding_hex.txt', '68690a', 'hex');
+            Node.FS.writeFileSync('./test_encoding_b64.txt', 'aGk=', { encoding: 'base64', flag: 'w' });
+            result = [
+                Node.FS.readFileSync('./test_encoding_utf8.txt', 'utf8'),
+                Node.FS.readFileSync('./test_encoding_utf8.txt').length,
+                Node.FS.readFileSync('./test_encoding_utf8.txt', { encoding: 'latin1' }).length,
+                Node.FS.readFileSync('./test_encoding_hex.txt', 'ascii'),
+                Node.FS.readFileSync('./test_encoding_b64.txt', 'hex'),
+                Node.FS.readFileSync('./test_encoding_utf8.txt', 'base64'),
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "héllo,6,6,hi\n,6869,aMOpbGxv");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_open_flags() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./test_flag_*");
+        config.fs.add_to_write_whitelist("./test_flag_*");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        // Opens a file that contains `old` (or doesn't exist), writes `new`
+        // and reads from the start, then reads the whole file back
+        ctx.eval_global("test.js", r#"
+            function check(flag, exists) {
+                const file = './test_flag_' + String(flag).replace(/\+/g, 'p') + '.txt';
+                Node.FS.rmSync(file, { force: true });
+                if (exists) {
+                    Node.FS.writeFileSync(file, 'old', 'w');
+                }
+                let fd;
+                try { fd = Node.FS.openSync(file, flag) } catch (e) { return e.code }
+                let out = [];
+                try { Node.FS.writeSync(fd, new Uint8Array([110, 101, 119])); out.push('w') } catch (e) { out.push('err') }
+                let buffer = new Uint8Array(16);
+                try {
+                    let n = Node.FS.readSync(fd, buffer, 0, 16, 0);
+                    out.push(String.fromCharCode(...buffer.subarray(0, n)));
+                } catch (e) {
+                    out.push('err');
+                }
+                Node.FS.closeSync(fd);
+                out.push(Node.FS.readFileSync(file, 'utf8'));
+                return out.join(':');
+            }
+            const { O_RDWR, O_WRONLY, O_CREAT, O_TRUNC, O_APPEND } = Node.FS.constants;
+            result = [
+                check('r', true),
+                check('r', false),
+                check('rs', true),
+                check('sr', true),
+                check('r+', true),
+                check('rs+', true),
+                check('sr+', true),
+                check('w', true),
+                check('wx', true),
+                check('wx', false),
+                check('xw', false),
+                check('w+', true),
+                check('wx+', false),
+                check('xw+', false),
+                check('a', true),
+                check('a', false),
+                check('ax', false),
+                check('xa', true),
+                check('as', true),
+                check('sa', true),
+                check('a+', true),
+                check('ax+', false),
+                check('xa+', false),
+                check('as+', true),
+                check('sa+', true),
+                check(O_WRONLY | O_CREAT | O_TRUNC, true),
+                check(O_RDWR | O_CREAT | O_APPEND, true),
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        let expected = [
+            "err:old:old",
+            "ENOENT",
+            "err:old:old",
+            "err:old:old",
+            "w:new:new",
+            "w:new:new",
+            "w:new:new",
+            "w:err:new",
+            "EEXIST",
+            "w:err:new",
+            "w:err:new",
+            "w:new:new",
+            "w:new:new",
+            "w:new:new",
+            "w:err:oldnew",
+            "w:err:new",
+            "w:err:new",
+            "EEXIST",
+            "w:err:oldnew",
+            "w:err:oldnew",
+            "w:oldnew:oldnew",
+            "w:new:new",
+            "w:new:new",
+            "w:oldnew:oldnew",
+            "w:oldnew:oldnew",
+            "w:err:new",
+            "w:oldnew:oldnew",
+        ];
+        assert_eq!(result, expected.join(","));
+
+        // Flags are still accepted in place of the options
+        ctx.eval_global("test.js", r#"
+            Node.FS.writeFileSync('./test_flag_legacy.txt', 'old', 'w');
+            Node.FS.writeFileSync('./test_flag_legacy.txt', 'new', 'as');
+            result = [
+                Node.FS.readFileSync('./test_flag_legacy.txt', 'r+').length,
+                Node.FS.readFileSync('./test_flag_legacy.txt', { flag: 'rs', encoding: 'utf8' }),
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "6,oldnew");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_streams() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./test_stream.txt");
+        config.fs.add_to_write_whitelist("./test_stream.txt");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            let events = [];
+            let out = Node.FS.createWriteStream('./test_stream.txt');
+            out.write('hello ');
+            out.end(new Uint8Array([119, 111, 114, 108, 100]), () => events.push('finish'));
+
+            async function read() {
+                let sizes = [];
+                for await (const chunk of Node.FS.createReadStream('./test_stream.txt', { highWaterMark: 4 })) {
+                    sizes.push(chunk.length);
+                }
+                let text = '';
+                let stream = Node.FS.createReadStream('./test_stream.txt', { start: 6, end: 8 });
+                stream.on('data', (chunk) => text += String.fromCharCode(...chunk));
+                stream.on('end', () => {
+                    result = [events.join('|'), out.bytesWritten, sizes.join('|'), text].join(',');
+                });
+            }
+            Promise.resolve().then(() => Promise.resolve()).then(read);
+        "#)?;
+        ctx.execute_pending()?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "finish,11,4|4|3,wor");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_promises() -> Result<()> {
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_to_read_whitelist("./test_promises*");
+        config.fs.add_to_write_whitelist("./test_promises.txt");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            const fs = Node.FS.promises;
+            let settled = false;
+            (async () => {
+                await fs.writeFile('./test_promises.txt', 'abc');
+                const text = await fs.readFile('./test_promises.txt', 'utf8');
+                const stats = await fs.stat('./test_promises.txt');
+                const handle = await fs.open('./test_promises.txt', 'r');
+                const { bytesRead } = await handle.read(new Uint8Array(8), 0, 8, 0);
+                await handle.close();
+                let code = await fs.readFile('./test_promises_missing.txt').catch((e) => e.code);
+                result = [text, stats.size, bytesRead, code].join(',');
+            })();
+            settled = typeof result !== 'undefined';
+        "#)?;
+        // Nothing settles before the pending jobs run
+        let settled = ctx.global_object()?.get_property("settled")?.as_bool()?;
+        assert!(!settled);
+        ctx.execute_pending()?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "abc,3,3,ENOENT");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_scratch_dir() -> Result<()> {
+        let scratch = "./test_scratch";
+        std::fs::create_dir_all(scratch)?;
+
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.set_scratch_dir(scratch, Some(8))?;
+        config.fs.add_to_write_blacklist("/tmp/*/secret.txt");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            const dir = Node.FS.mkdtempSync(Node.FS.tmpdir() + '/node-');
+            Node.FS.writeFileSync(dir + '/a.txt', 'abcdef');
+            let full = 'none';
+            try { Node.FS.writeFileSync(dir + '/b.txt', 'abcdef') } catch (e) { full = e.code }
+            // Removing a file frees its space
+            Node.FS.unlinkSync(dir + '/a.txt');
+            Node.FS.writeFileSync(dir + '/b.txt', 'abcdef');
+            let blacklisted = 'none';
+            try { Node.FS.writeFileSync(dir + '/secret.txt', 'a') } catch (e) { blacklisted = e.code }
+            let outside = 'none';
+            try { Node.FS.writeFileSync('./test_scratch_outside.txt', 'a') } catch (e) { outside = e.code }
+            result = [
+                Node.FS.tmpdir(),
+                dir.startsWith('/tmp/node-'),
+                Node.FS.readFileSync(dir + '/b.txt', 'utf8'),
+                full,
+                blacklisted,
+                outside,
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "/tmp,true,abcdef,ENOSPC,EACCES,EACCES");
+
+        config.fs.wipe_scratch()?;
+        assert_eq!(std::fs::read_dir(scratch)?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_mounts() -> Result<()> {
+        std::fs::create_dir_all("./test_mount_input")?;
+        std::fs::create_dir_all("./test_mount_output")?;
+        std::fs::write("./test_mount_input/in.txt", "input")?;
+
+        let runtime = Runtime::default();
+        let config = &mut APIConfig::default();
+        config.fs.add_mount("/data", "./test_mount_input", MountMode::ReadOnly)?;
+        config.fs.add_mount("/out", "./test_mount_output", MountMode::ReadWrite)?;
+        config.fs.add_to_read_whitelist("/data/*");
+        config.fs.add_to_read_whitelist("/out");
+        config.fs.add_to_read_whitelist("/out/*");
+        config.fs.add_to_write_whitelist("/data/*");
+        config.fs.add_to_write_whitelist("/out/*");
+        config.fs.add_to_write_whitelist("./*");
+
+        FS.register(&runtime, config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            Node.FS.writeFileSync('/out/result.txt', Node.FS.readFileSync('/data/in.txt', 'utf8') + '!');
+            let readOnly = 'none';
+            try { Node.FS.writeFileSync('/data/in.txt', 'changed') } catch (e) { readOnly = e.code }
+            let host = 'none';
+            try { Node.FS.readFileSync('./test_mount_input/in.txt') } catch (e) { host = e.code }
+            let hostWrite = 'none';
+            try { Node.FS.writeFileSync('./test_mount_input/in.txt', 'changed') } catch (e) { hostWrite = e.code }
+            result = [
+                Node.FS.readFileSync('/out/result.txt', 'utf8'),
+                Node.FS.readdirSync('/out').join('|'),
+                Node.FS.mkdirSync('/out/a/b', { recursive: true }),
+                readOnly,
+                host,
+                hostWrite,
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "input!,result.txt,/out/a,EROFS,EACCES,EACCES");
+        assert_eq!(std::fs::read_to_string("./test_mount_output/result.txt")?, "input!");
+        assert_eq!(std::fs::read_to_string("./test_mount_input/in.txt")?, "input");
+
+        std::fs::remove_dir_all("./test_mount_input")?;
+        std::fs::remove_dir_all("./test_mount_output")?;
+        Ok(())
+    }
 }
@@ -0,0 +1,139 @@
+//! Mount table mapping the stable paths seen by JS to the directories the
+//! host preopened, e.g. `/data` to the preopen `input`.
+//!
+//! The glob lists of [`super::FSConfig`] match the virtual paths, the I/O is
+//! done on the host paths, and the access mode of a mount is enforced on top
+//! of the globs: nothing below a read-only mount can be written. The host
+//! directories themselves can't be addressed by their host paths.
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountMode {
+    ReadOnly,
+    ReadWrite,
+}
+
+impl TryFrom<&str> for MountMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "ro" => Ok(MountMode::ReadOnly),
+            "rw" => Ok(MountMode::ReadWrite),
+            _ => anyhow::bail!("Invalid mount mode: {}, expected ro or rw", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Mount {
+    virtual_path: PathBuf,
+    host_path: PathBuf,
+    mode: MountMode,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+impl MountTable {
+    /// Mounts `host_path` at `virtual_path`, which must be absolute. A path
+    /// mounted twice keeps its last mount.
+    pub(super) fn add(&mut self, virtual_path: &str, host_path: &str, mode: MountMode) -> io::Result<()> {
+        let virtual_path = path::normalize(Path::new(virtual_path));
+        if virtual_path.is_relative() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("mount point {} is not absolute", virtual_path.display()),
+            ));
+        }
+        self.mounts.retain(|mount| mount.virtual_path != virtual_path);
+        self.mounts.push(Mount {
+            virtual_path,
+            host_path: path::normalize(Path::new(host_path)),
+            mode,
+        });
+        Ok(())
+    }
+
+    /// The innermost mount containing the virtual path `path`.
+    fn find(&self, path: &Path) -> Option<&Mount> {
+        self.mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.virtual_path))
+            .max_by_key(|mount| mount.virtual_path.components().count())
+    }
+
+    /// Host path to do the I/O on for the normalized virtual path `path`.
+    /// Paths outside of every mount are left as they are.
+    pub(super) fn to_host(&self, path: &Path) -> PathBuf {
+        match self.find(path) {
+            Some(mount) => {
+                let rest = path.strip_prefix(&mount.virtual_path).unwrap_or(path);
+                path::normalize(&mount.host_path.join(rest))
+            }
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Virtual path of the normalized host path `path`, the reverse of
+    /// [`MountTable::to_host`].
+    pub(super) fn to_virtual(&self, path: &Path) -> PathBuf {
+        let mount = self
+            .mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.host_path))
+            .max_by_key(|mount| mount.host_path.components().count());
+        match mount {
+            Some(mount) => {
+                let rest = path.strip_prefix(&mount.host_path).unwrap_or(path);
+                path::normalize(&mount.virtual_path.join(rest))
+            }
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Whether `path` is outside of every mount but inside the host directory
+    /// of one, which is only reachable through its mount point.
+    pub(super) fn is_host_path(&self, path: &Path) -> bool {
+        self.find(path).is_none() && self.mounts.iter().any(|mount| path.starts_with(&mount.host_path))
+    }
+
+    /// Whether the virtual path `path` is below a read-only mount.
+    pub(super) fn is_read_only(&self, path: &Path) -> bool {
+        self.find(path)
+            .is_some_and(|mount| mount.mode == MountMode::ReadOnly)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MountMode, MountTable};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_mount_table() {
+        let mut mounts = MountTable::default();
+        mounts.add("/data", "input", MountMode::ReadOnly).unwrap();
+        mounts.add("/data/out", "output", MountMode::ReadWrite).unwrap();
+        assert!(mounts.add("data", "input", MountMode::ReadOnly).is_err());
+
+        assert_eq!(mounts.to_host(Path::new("/data/a.txt")), PathBuf::from("input/a.txt"));
+        assert_eq!(mounts.to_host(Path::new("/data")), PathBuf::from("input"));
+        assert_eq!(mounts.to_host(Path::new("/data/out/b")), PathBuf::from("output/b"));
+        assert_eq!(mounts.to_host(Path::new("/database")), PathBuf::from("/database"));
+        assert_eq!(mounts.to_virtual(Path::new("output/b")), PathBuf::from("/data/out/b"));
+
+        assert!(mounts.is_read_only(Path::new("/data/a.txt")));
+        assert!(!mounts.is_read_only(Path::new("/data/out/b")));
+        assert!(!mounts.is_read_only(Path::new("/elsewhere")));
+        assert!(mounts.is_host_path(Path::new("input/a.txt")));
+        assert!(!mounts.is_host_path(Path::new("/data/a.txt")));
+        assert!(!mounts.is_host_path(Path::new("inputs")));
+        assert!(MountMode::try_from("rx").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Path handling for [`super::FSConfig`] checks.
+//!
+//! Paths coming from JS are normalized lexically before being matched, so
+//! `./a/../../etc/passwd` is evaluated as `../etc/passwd` and not as
+//! something under `./`. Symlinks are resolved by hand since
+//! `std::fs::canonicalize` is not available on WASI.
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+// Same limit as Linux before it reports ELOOP
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Normalizes `path` without touching the filesystem: `.` components are
+/// dropped and `..` removes the previous component. Leading `..` that can't
+/// be removed are kept, see [`escapes`].
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => pop(&mut normalized),
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
+    }
+    normalized
+}
+
+/// Whether a normalized relative path points above the directory it is
+/// relative to.
+pub fn escapes(path: &Path) -> bool {
+    matches!(path.components().next(), Some(Component::ParentDir))
+}
+
+fn pop(path: &mut PathBuf) {
+    match path.components().next_back() {
+        Some(Component::Normal(_)) => {
+            path.pop();
+        }
+        // `/..` is `/`
+        Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+        Some(Component::ParentDir) | None | Some(Component::CurDir) => path.push(".."),
+    }
+}
+
+/// Resolves every symlink in the normalized `path`. Components that do not
+/// exist yet (for example a file about to be created) are kept as they are.
+pub fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut resolved = PathBuf::new();
+    let mut pending: VecDeque<OsString> = path
+        .components()
+        .map(|c| c.as_os_str().to_os_string())
+        .collect();
+    let mut hops = 0;
+
+    while let Some(component) = pending.pop_front() {
+        match Path::new(&component).components().next() {
+            Some(Component::CurDir) | None => continue,
+            Some(Component::ParentDir) => {
+                pop(&mut resolved);
+                continue;
+            }
+            Some(Component::RootDir) | Some(Component::Prefix(_)) => {
+                resolved = PathBuf::from(&component);
+                continue;
+            }
+            Some(Component::Normal(_)) => {}
+        }
+
+        let candidate = resolved.join(&component);
+        match std::fs::symlink_metadata(&candidate) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                hops += 1;
+                if hops > MAX_SYMLINK_HOPS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "too many levels of symbolic links",
+                    ));
+                }
+                let target = std::fs::read_link(&candidate)?;
+                // Absolute targets restart from the root, relative ones from
+                // the directory holding the link
+                for c in target.components().rev() {
+                    pending.push_front(c.as_os_str().to_os_string());
+                }
+            }
+            Ok(_) => resolved = candidate,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // Nothing below a missing entry can be a symlink
+                resolved = candidate;
+                for rest in pending.drain(..) {
+                    resolved.push(rest);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(normalize(&resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escapes, normalize};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new("./a/b/../c")), PathBuf::from("a/c"));
+        assert_eq!(normalize(Path::new("./")), PathBuf::from("."));
+        assert_eq!(normalize(Path::new("./a/../../etc/passwd")), PathBuf::from("../etc/passwd"));
+        assert_eq!(normalize(Path::new("/a/../../etc")), PathBuf::from("/etc"));
+        assert!(escapes(&normalize(Path::new("./a/../../etc/passwd"))));
+        assert!(!escapes(&normalize(Path::new("./a/../b"))));
+    }
+}
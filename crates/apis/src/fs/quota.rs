@@ -0,0 +1,79 @@
+//! Optional limits on how much `Node.FS` can write, enforced on top of the
+//! path permissions of [`super::FSConfig`]. Going over a limit throws an
+//! `EDQUOT` error.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteLimits {
+    /// Bytes that can be written until the usage is reset.
+    pub max_bytes_per_invocation: Option<u64>,
+    /// Size a file can grow to through writes.
+    pub max_file_size: Option<u64>,
+    /// Files and directories that can be created until the usage is reset.
+    pub max_files_created: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    bytes_written: u64,
+    files_created: u64,
+}
+
+/// What was written since the last [`WriteUsage::reset`]. All the clones of
+/// a config share the same usage, so the embedder can keep a handle to reset
+/// it when a new invocation starts.
+#[derive(Debug, Clone, Default)]
+pub struct WriteUsage(Rc<RefCell<Usage>>);
+
+impl WriteUsage {
+    pub fn reset(&self) {
+        *self.0.borrow_mut() = Usage::default();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct WriteQuota {
+    pub(super) limits: WriteLimits,
+    pub(super) usage: WriteUsage,
+}
+
+impl WriteQuota {
+    pub(super) fn is_limited(&self) -> bool {
+        let limits = &self.limits;
+        limits.max_bytes_per_invocation.is_some()
+            || limits.max_file_size.is_some()
+            || limits.max_files_created.is_some()
+    }
+
+    /// Accounts for writing `len` bytes to a file that is `new_size` bytes
+    /// long afterwards, returns `false` without counting them if that goes
+    /// over a limit.
+    pub(super) fn reserve_write(&self, len: u64, new_size: u64) -> bool {
+        let mut usage = self.usage.0.borrow_mut();
+        let bytes_written = usage.bytes_written + len;
+        let over = self
+            .limits
+            .max_bytes_per_invocation
+            .is_some_and(|max| bytes_written > max)
+            || self.limits.max_file_size.is_some_and(|max| new_size > max);
+        if !over {
+            usage.bytes_written = bytes_written;
+        }
+        !over
+    }
+
+    /// Whether `count` more files or directories can be created.
+    pub(super) fn can_create(&self, count: u64) -> bool {
+        let usage = self.usage.0.borrow();
+        !self
+            .limits
+            .max_files_created
+            .is_some_and(|max| usage.files_created + count > max)
+    }
+
+    /// Accounts for files or directories that were created, once they are.
+    pub(super) fn count_created(&self, count: u64) {
+        self.usage.0.borrow_mut().files_created += count;
+    }
+}
@@ -0,0 +1,100 @@
+//! Private scratch area handed out by `Node.FS.tmpdir()` and
+//! `Node.FS.mkdtempSync()`.
+//!
+//! The area is a directory designated by the host (usually a preopen). Paths
+//! below it are readable and writable without any whitelist entry unless
+//! they are blacklisted, the total size of its files can be capped, and the
+//! embedder wipes it between invocations with
+//! [`super::FSConfig::wipe_scratch`].
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const SUFFIX_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// Where relative scratch areas are served, like Node's `tmpdir()` on Linux.
+pub(super) const MOUNT_POINT: &str = "/tmp";
+
+#[derive(Debug, Clone)]
+pub(super) struct ScratchDir {
+    /// Absolute path seen by JS.
+    pub(super) path: PathBuf,
+    pub(super) max_bytes: Option<u64>,
+    pub(super) usage: ScratchUsage,
+}
+
+/// Bytes taken by the files of the area, counted as they are written,
+/// emptied and removed rather than by walking the area. All the clones of a
+/// config share the same usage.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ScratchUsage(Rc<Cell<u64>>);
+
+impl ScratchUsage {
+    pub(super) fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    pub(super) fn grow(&self, bytes: u64) {
+        self.0.set(self.0.get() + bytes);
+    }
+
+    pub(super) fn shrink(&self, bytes: u64) {
+        self.0.set(self.0.get().saturating_sub(bytes));
+    }
+
+    pub(super) fn reset(&self) {
+        self.0.set(0);
+    }
+}
+
+/// Size of the file at `path`, or total size of the files below it when it
+/// is a directory.
+pub(super) fn size(path: &Path) -> io::Result<u64> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        total += size(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Removes everything below `dir`, keeping the directory itself.
+pub(super) fn wipe(dir: &Path) -> io::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if std::fs::symlink_metadata(&path)?.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Six random characters appended to the prefix of `mkdtempSync`, like Node.
+pub(super) fn random_suffix() -> String {
+    // `RandomState` is seeded from the host's randomness
+    let mut n = RandomState::new().build_hasher().finish();
+    (0..6)
+        .map(|_| {
+            let c = SUFFIX_CHARS[(n % SUFFIX_CHARS.len() as u64) as usize];
+            n /= SUFFIX_CHARS.len() as u64;
+            c as char
+        })
+        .collect()
+}
@@ -0,0 +1,101 @@
+//! Conversion of [`std::fs::Metadata`] into the raw object `fs.js` wraps in a
+//! Node `Stats`.
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::time::SystemTime;
+
+use javy::quickjs::JSValue;
+
+use super::embedded::Entry;
+
+fn millis(time: std::io::Result<SystemTime>) -> f64 {
+    time.ok()
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+fn file_type(metadata: &Metadata) -> &'static str {
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    }
+}
+
+#[cfg(unix)]
+fn host_fields(metadata: &Metadata, stats: &mut HashMap<&str, JSValue>) {
+    use std::os::unix::fs::MetadataExt;
+
+    stats.insert("dev", (metadata.dev() as f64).into());
+    stats.insert("ino", (metadata.ino() as f64).into());
+    stats.insert("mode", (metadata.mode() as f64).into());
+    stats.insert("nlink", (metadata.nlink() as f64).into());
+    stats.insert("uid", (metadata.uid() as f64).into());
+    stats.insert("gid", (metadata.gid() as f64).into());
+    stats.insert("ctimeMs", (metadata.ctime() as f64 * 1000.0 + metadata.ctime_nsec() as f64 / 1e6).into());
+}
+
+#[cfg(not(unix))]
+fn host_fields(metadata: &Metadata, stats: &mut HashMap<&str, JSValue>) {
+    // File type bits of `st_mode`
+    const S_IFREG: u32 = 0o100000;
+    const S_IFDIR: u32 = 0o040000;
+    const S_IFLNK: u32 = 0o120000;
+
+    // WASI does not expose the mode, derive one from the file type
+    let file_type = metadata.file_type();
+    let mut mode = if metadata.permissions().readonly() { 0o444 } else { 0o666 };
+    if file_type.is_symlink() {
+        mode |= S_IFLNK;
+    } else if file_type.is_dir() {
+        mode |= S_IFDIR | 0o111;
+    } else {
+        mode |= S_IFREG;
+    }
+
+    stats.insert("dev", 0.0.into());
+    stats.insert("ino", 0.0.into());
+    stats.insert("mode", (mode as f64).into());
+    stats.insert("nlink", 1.0.into());
+    stats.insert("uid", 0.0.into());
+    stats.insert("gid", 0.0.into());
+    // There is no status change time, the last modification is the closest
+    stats.insert("ctimeMs", millis(metadata.modified()).into());
+}
+
+/// Returns the fields of a Node `Stats` object for `metadata`.
+pub(super) fn metadata_to_js(metadata: &Metadata) -> JSValue {
+    let mut stats: HashMap<&str, JSValue> = HashMap::from([
+        ("size", (metadata.len() as f64).into()),
+        ("atimeMs", millis(metadata.accessed()).into()),
+        ("mtimeMs", millis(metadata.modified()).into()),
+        ("birthtimeMs", millis(metadata.created()).into()),
+        ("type", file_type(metadata).into()),
+    ]);
+    host_fields(metadata, &mut stats);
+    JSValue::from_hashmap(stats)
+}
+
+/// Returns the fields of a Node `Stats` object for a file embedded in the
+/// module, which is read-only and has no meaningful times.
+pub(super) fn embedded_to_js(entry: &Entry) -> JSValue {
+    let (size, mode, file_type) = match entry {
+        Entry::File(contents) => (contents.len() as f64, 0o100444, "file"),
+        Entry::Directory => (0.0, 0o040555, "directory"),
+    };
+    let mut stats: HashMap<&str, JSValue> = HashMap::from([
+        ("size", size.into()),
+        ("mode", (mode as f64).into()),
+        ("type", file_type.into()),
+        ("nlink", 1.0.into()),
+    ]);
+    for field in ["dev", "ino", "uid", "gid", "atimeMs", "mtimeMs", "ctimeMs", "birthtimeMs"] {
+        stats.insert(field, 0.0.into());
+    }
+    JSValue::from_hashmap(stats)
+}
@@ -13,9 +13,18 @@
 
     globalThis.require = function (name) {
 
-        if(name === "fs") {
+        if(name === "fs" || name === "node:fs") {
             return Node.FS;
         }
+        if(name === "fs/promises" || name === "node:fs/promises") {
+            return Node.FS.promises;
+        }
+        if(name === "os" || name === "node:os") {
+            return {
+                EOL: "\n",
+                tmpdir: () => Node.FS.tmpdir(),
+            };
+        }
         return null;
     }
   })();
@@ -0,0 +1,197 @@
+//! Sends requests through the host within the limits of [`HTTPConfig`].
+//!
+//! The host never follows redirects: they are followed here, so every target
+//! is checked with [`HTTPConfig::can_access`] and counted against the
+//! redirect policy, and the timeout covers the whole chain. Each request of
+//! the chain is recorded or replayed on its own, and takes a token from the
+//! rate limits of the rules allowing it.
+use anyhow::Result;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use super::config::{RedirectPolicy, MAX_REDIRECTS};
+use super::error::{self, HttpError};
+use super::headers;
+use super::host::{Request, Response};
+use super::rate_limit;
+use super::HTTPConfig;
+
+const REDIRECT_STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+
+/// What to do with redirect responses, the `redirect` option of `fetch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RedirectMode {
+    /// Follow them as far as the redirect policy allows.
+    Follow,
+    /// Return them as they are.
+    Manual,
+    /// Fail on them.
+    Error,
+}
+
+impl TryFrom<&str> for RedirectMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "follow" => Ok(RedirectMode::Follow),
+            "manual" => Ok(RedirectMode::Manual),
+            "error" => Ok(RedirectMode::Error),
+            _ => anyhow::bail!("Invalid redirect mode: {}", value),
+        }
+    }
+}
+
+/// The last response of a request and the URL it came from.
+pub(super) struct Completed {
+    pub(super) response: Response,
+    pub(super) url: String,
+    pub(super) redirected: bool,
+}
+
+pub(super) fn send(config: &HTTPConfig, mut request: Request, mode: RedirectMode) -> Result<Completed> {
+    let limits = config.limits();
+    if !config.can_access(&request.method, &request.url) {
+        return Err(HttpError::access_denied(&request.method, &request.url).into());
+    }
+    if let Some(max) = limits.max_request_bytes {
+        if request.body.len() as u64 > max {
+            return Err(HttpError::new(
+                error::REQUEST_TOO_LARGE,
+                format!("Request body of {} bytes is over the limit of {} bytes", request.body.len(), max),
+            )
+            .into());
+        }
+    }
+
+    let started = Instant::now();
+    // `can_access` only allows URLs that parse
+    let origin = Url::parse(&request.url)?.origin();
+    let mut redirects = 0;
+    loop {
+        // Waiting for the rate limit counts against the timeout
+        let remaining = limits
+            .timeout_ms
+            .map(|timeout_ms| Duration::from_millis(timeout_ms).saturating_sub(started.elapsed()));
+        let _permit = rate_limit::acquire(
+            config.limiters(&request.method, &request.url),
+            limits.rate_limited,
+            remaining,
+            &request.method,
+            &request.url,
+        )?;
+        if let Some(timeout_ms) = limits.timeout_ms {
+            let remaining = Duration::from_millis(timeout_ms).saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return Err(HttpError::new(error::TIMEOUT, format!("Request timed out after {} ms", timeout_ms)).into());
+            }
+            request.timeout_ms = Some(remaining.as_millis().max(1) as u64);
+        }
+        request.max_response_bytes = limits.max_response_bytes;
+
+        // Applied to every request of the chain, so injected values only go
+        // to the URLs their policy matches
+        let outgoing = headers::apply(config.header_policies(), &request)?;
+        let response = config.fixtures().send(&outgoing)?;
+        if let Some(max) = limits.max_response_bytes {
+            // The host should have given up already
+            if response.body.len() as u64 > max {
+                return Err(HttpError::new(
+                    error::RESPONSE_TOO_LARGE,
+                    format!("Response body is over the limit of {} bytes", max),
+                )
+                .into());
+            }
+        }
+
+        let location = match location(&response) {
+            Some(location) if mode != RedirectMode::Manual => location,
+            _ => {
+                return Ok(Completed {
+                    response,
+                    url: request.url,
+                    redirected: redirects > 0,
+                })
+            }
+        };
+        let max_redirects = match (mode, limits.redirect) {
+            (RedirectMode::Error, _) => {
+                return Err(HttpError::new(
+                    error::REDIRECT_DENIED,
+                    format!("Redirect from {} with redirect mode error", request.url),
+                )
+                .into())
+            }
+            (_, RedirectPolicy::None) => {
+                return Ok(Completed {
+                    response,
+                    url: request.url,
+                    redirected: redirects > 0,
+                })
+            }
+            (_, RedirectPolicy::SameOrigin) => MAX_REDIRECTS,
+            (_, RedirectPolicy::Follow(max)) => max,
+        };
+
+        let current = Url::parse(&request.url)?;
+        let target = current.join(&location).map_err(|_| {
+            HttpError::new(error::REDIRECT_DENIED, format!("Invalid redirect location: {}", location))
+        })?;
+        if redirects >= max_redirects {
+            return Err(HttpError::new(
+                error::TOO_MANY_REDIRECTS,
+                format!("More than {} redirects, last to {}", max_redirects, target),
+            )
+            .into());
+        }
+        if limits.redirect == RedirectPolicy::SameOrigin && target.origin() != origin {
+            return Err(HttpError::new(
+                error::REDIRECT_DENIED,
+                format!("Redirect to {} leaves the origin of the request", target),
+            )
+            .into());
+        }
+
+        redirect(&mut request, response.status, &current, &target);
+        if !config.can_access(&request.method, &request.url) {
+            return Err(HttpError::access_denied(&request.method, &request.url).into());
+        }
+        redirects += 1;
+    }
+}
+
+fn location(response: &Response) -> Option<String> {
+    if !REDIRECT_STATUSES.contains(&response.status) {
+        return None;
+    }
+    response
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+        .map(|(_, value)| value.clone())
+}
+
+/// Turns `request` into the request to `target`, like the fetch standard:
+/// 303s and POSTs redirected by 301 or 302 become GETs without a body, and
+/// credentials are not sent to another origin.
+fn redirect(request: &mut Request, status: u16, current: &Url, target: &Url) {
+    let to_get = (status == 303 && request.method != "HEAD")
+        || (matches!(status, 301 | 302) && request.method == "POST");
+    if to_get {
+        request.method = "GET".to_string();
+        request.body.clear();
+        request.headers.retain(|(name, _)| {
+            let name = name.to_ascii_lowercase();
+            !matches!(
+                name.as_str(),
+                "content-type" | "content-length" | "content-encoding" | "content-language" | "content-location"
+            )
+        });
+    }
+    if target.origin() != current.origin() {
+        request
+            .headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("authorization"));
+    }
+    request.url = target.to_string();
+}
@@ -1,17 +1,74 @@
 use std::collections::HashSet;
-use regex::Regex;
 use std::hash::Hash;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use regex::Regex;
+use url::{Host, Url};
+
+use super::fixtures::Fixtures;
+pub use super::headers::{HeaderPolicy, SecretSource};
+use super::rate_limit::Limiter;
+pub use super::rate_limit::RateLimit;
+
+/// What the host of a rule matches: a regex over domain names (and the text
+/// of IP literals), or an IP network written as `10.0.0.0/8`, `::1/128` or a
+/// plain address.
+#[derive(Clone, Debug)]
+enum HostPattern {
+    Name(Regex),
+    Network(IpAddr, u8),
+}
+
+/// The domain and endpoint patterns of access rules and header policies.
+#[derive(Clone, Debug)]
+pub(super) struct UrlPattern {
+    domain_pattern: HostPattern,
+    endpoint_pattern: Regex,
+}
+
+impl UrlPattern {
+    /// Returns `None` when one of the patterns is not a valid regex. A
+    /// domain pattern that parses as an IP address or network matches IP
+    /// hosts by address.
+    pub(super) fn new(domain_pattern: &str, endpoint_pattern: &str) -> Option<Self> {
+        let domain_pattern = match parse_network(domain_pattern) {
+            Some((addr, prefix)) => HostPattern::Network(addr, prefix),
+            None => HostPattern::Name(anchored(domain_pattern, true)?),
+        };
+        Some(UrlPattern {
+            domain_pattern,
+            endpoint_pattern: anchored(endpoint_pattern, false)?,
+        })
+    }
+
+    pub(super) fn matches(&self, url: &Url) -> bool {
+        let host_matches = match (&self.domain_pattern, url.host()) {
+            (HostPattern::Name(regex), Some(Host::Domain(domain))) => regex.is_match(domain),
+            (HostPattern::Name(regex), Some(Host::Ipv4(ip))) => regex.is_match(&ip.to_string()),
+            (HostPattern::Name(regex), Some(Host::Ipv6(ip))) => regex.is_match(&ip.to_string()),
+            (HostPattern::Network(network, prefix), Some(Host::Ipv4(ip))) => {
+                network_contains(*network, *prefix, IpAddr::V4(ip))
+            }
+            (HostPattern::Network(network, prefix), Some(Host::Ipv6(ip))) => {
+                network_contains(*network, *prefix, IpAddr::V6(ip))
+            }
+            _ => false,
+        };
+        host_matches && self.endpoint_pattern.is_match(url.path())
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct HttpAccessRule {
     method: String,
     domain_pattern_str: String,
     endpoint_pattern_str: String,
-    domain_pattern: Regex,
-    endpoint_pattern: Regex,
+    pattern: UrlPattern,
     method_pattern: Regex,
+    scheme: Option<String>,
+    port: Option<u16>,
+    limiter: Limiter,
 }
-use url::Url;
 
 impl Eq for HttpAccessRule {}
 
@@ -19,7 +76,11 @@ impl PartialEq for HttpAccessRule {
     fn eq(&self, other: &Self) -> bool {
         self.method == other.method &&
         self.domain_pattern_str == other.domain_pattern_str &&
-        self.endpoint_pattern_str == other.endpoint_pattern_str
+        self.endpoint_pattern_str == other.endpoint_pattern_str &&
+        self.scheme == other.scheme &&
+        self.port == other.port &&
+        self.limiter.rate == other.limiter.rate &&
+        self.limiter.max_concurrent == other.limiter.max_concurrent
     }
 }
 
@@ -28,34 +89,210 @@ impl Hash  for HttpAccessRule {
         self.method.hash(state);
         self.domain_pattern_str.hash(state);
         self.endpoint_pattern_str.hash(state);
+        self.scheme.hash(state);
+        self.port.hash(state);
+        if let Some(rate) = self.limiter.rate {
+            rate.requests_per_second.to_bits().hash(state);
+            rate.burst.hash(state);
+        }
+        self.limiter.max_concurrent.hash(state);
+    }
+}
+
+/// Patterns must match the whole value, so `example\.com` does not match
+/// `evil-example.com.attacker`.
+fn anchored(pattern: &str, case_insensitive: bool) -> Option<Regex> {
+    let flags = if case_insensitive { "(?i)" } else { "" };
+    Regex::new(&format!("{}^(?:{})$", flags, pattern)).ok()
+}
+
+fn parse_network(pattern: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match pattern.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (pattern, None),
+    };
+    let addr: IpAddr = addr.trim_start_matches('[').trim_end_matches(']').parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max)?,
+        None => max,
+    };
+    Some((addr, prefix))
+}
+
+fn network_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    // `[::ffff:10.0.0.1]` is the IPv4 address `10.0.0.1`
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    };
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
     }
 }
 
 impl HttpAccessRule {
+    /// Returns `None` when one of the patterns is not a valid regex. The
+    /// method pattern is case insensitive, and a domain pattern that parses
+    /// as an IP address or network matches IP hosts by address.
     pub fn new(method: &str, domain_pattern: &str, endpoint_pattern: &str) -> Option<Self> {
-        let domain_regex = Regex::new(domain_pattern).unwrap();
-        let endpoint_regex = Regex::new(endpoint_pattern).unwrap();
-        let method_regex = Regex::new(method).unwrap();
-        
+        let pattern = UrlPattern::new(domain_pattern, endpoint_pattern)?;
+        let method_regex = anchored(method, true)?;
+
         Some(HttpAccessRule {
             method: method.to_uppercase(),
             domain_pattern_str: domain_pattern.to_string(),
             endpoint_pattern_str: endpoint_pattern.to_string(),
-            domain_pattern: domain_regex,
-            endpoint_pattern: endpoint_regex,
+            pattern,
             method_pattern: method_regex,
+            scheme: None,
+            port: None,
+            limiter: Limiter::default(),
         })
     }
+
+    /// Only matches URLs with this scheme, e.g. `https`.
+    pub fn with_scheme(mut self, scheme: &str) -> Self {
+        self.scheme = Some(scheme.to_ascii_lowercase());
+        self
+    }
+
+    /// Only matches URLs with this port, the scheme's default port when the
+    /// URL has none.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Limits the requests this rule allows, shared by every clone of the
+    /// rule. Requests over the limit wait or are rejected depending on
+    /// [`HttpLimits::rate_limited`].
+    pub fn with_rate_limit(mut self, rate: RateLimit) -> Self {
+        self.limiter.rate = Some(rate);
+        self
+    }
+
+    /// Rejects requests allowed by this rule with `ERR_HTTP_RATE_LIMITED`
+    /// while `max` of them are in flight, whatever
+    /// [`HttpLimits::rate_limited`] says.
+    pub fn with_max_concurrent(mut self, max: u32) -> Self {
+        self.limiter.max_concurrent = Some(max);
+        self
+    }
+
+    fn matches(&self, method: &str, url: &Url) -> bool {
+        if self.scheme.as_deref().is_some_and(|scheme| scheme != url.scheme()) {
+            return false;
+        }
+        if self.port.is_some() && self.port != url.port_or_known_default() {
+            return false;
+        }
+        self.method_pattern.is_match(method) && self.pattern.matches(url)
+    }
+}
+
+/// Redirects followed by default, the limit of the fetch standard.
+pub const MAX_REDIRECTS: u32 = 20;
+
+/// Which redirect responses are followed. Every target must also be allowed
+/// by the access rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Redirect responses are returned as they are.
+    None,
+    /// Redirects to the origin of the first request are followed, up to
+    /// [`MAX_REDIRECTS`] of them.
+    SameOrigin,
+    /// Up to this many redirects are followed.
+    Follow(u32),
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy::Follow(MAX_REDIRECTS)
+    }
+}
+
+impl TryFrom<&str> for RedirectPolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "none" => Ok(RedirectPolicy::None),
+            "same-origin" => Ok(RedirectPolicy::SameOrigin),
+            "follow" => Ok(RedirectPolicy::default()),
+            _ => anyhow::bail!("Invalid redirect policy: {}, expected none, same-origin or follow", value),
+        }
+    }
+}
+
+/// What happens to requests over the rate limit of a rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// They wait for the bucket to refill, unless that takes longer than
+    /// the timeout.
+    #[default]
+    Wait,
+    /// They are rejected with `ERR_HTTP_RATE_LIMITED`.
+    Reject,
+}
+
+impl TryFrom<&str> for RateLimitPolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "wait" => Ok(RateLimitPolicy::Wait),
+            "reject" => Ok(RateLimitPolicy::Reject),
+            _ => anyhow::bail!("Invalid rate limit policy: {}, expected wait or reject", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HttpLimits {
+    /// Time a request can take, redirects included.
+    pub timeout_ms: Option<u64>,
+    /// Size of the request body.
+    pub max_request_bytes: Option<u64>,
+    /// Size of each response body.
+    pub max_response_bytes: Option<u64>,
+    pub redirect: RedirectPolicy,
+    pub rate_limited: RateLimitPolicy,
+}
+
+/// Where the responses come from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HttpMode {
+    /// From the host.
+    #[default]
+    Live,
+    /// From the host, every request and response being appended to this
+    /// fixture file.
+    Record(PathBuf),
+    /// From this fixture file, the host is never called.
+    Replay(PathBuf),
 }
 
 #[derive(Debug, Clone)]
 pub struct HTTPConfig {
     pub allowed_rules: HashSet<HttpAccessRule>,
+    limits: HttpLimits,
+    header_policies: Vec<HeaderPolicy>,
+    fixtures: Fixtures,
 }
 
 impl Default for HTTPConfig {
     fn default() -> Self {
-        let mut config = HTTPConfig::new();
+        let config = HTTPConfig::new();
         // config.allow_access("GET", ".*", ".*");
         // config.allow_access("POST", ".*", ".*");
         // config.allow_access("PUT", ".*", ".*");
@@ -68,61 +305,138 @@ impl HTTPConfig {
     pub fn new() -> Self {
         HTTPConfig {
             allowed_rules: HashSet::new(),
+            limits: HttpLimits::default(),
+            header_policies: Vec::new(),
+            fixtures: Fixtures::default(),
         }
     }
 
+    /// Policies are applied in the order they were added to the requests
+    /// whose URL they match.
+    pub fn add_header_policy(&mut self, policy: HeaderPolicy) {
+        self.header_policies.push(policy);
+    }
+
+    pub(super) fn header_policies(&self) -> &[HeaderPolicy] {
+        &self.header_policies
+    }
+
+    /// Records or replays the responses, the access rules and limits apply
+    /// in every mode.
+    pub fn set_mode(&mut self, mode: HttpMode) {
+        self.fixtures = Fixtures::new(mode);
+    }
+
+    pub fn mode(&self) -> &HttpMode {
+        self.fixtures.mode()
+    }
+
+    pub(super) fn fixtures(&self) -> &Fixtures {
+        &self.fixtures
+    }
+
+    pub fn set_limits(&mut self, limits: HttpLimits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &HttpLimits {
+        &self.limits
+    }
+
+    /// Allows requests matching all the patterns, invalid rules are ignored.
     pub fn allow_access(&mut self, method: &str, domain_pattern: &str, endpoint_pattern: &str) {
         if let Some(rule) = HttpAccessRule::new(method, domain_pattern, endpoint_pattern) {
-            self.allowed_rules.insert(rule);
+            self.add_rule(rule);
         }
     }
 
-    pub fn can_access(&self, method: &str,endpoint: &str) -> bool {
-        // Separate endpoint and domain
-        // TODO: This is not a good way to do this
+    pub fn add_rule(&mut self, rule: HttpAccessRule) {
+        self.allowed_rules.insert(rule);
+    }
 
-        let url = Url::parse(&endpoint).unwrap();
-        eprintln!("Domain: {}, Endpoint: {}", url.domain().expect("Cannot parse domain"), url.path());
-        let domain = url.domain().expect("Cannot parse domain");
-        let endpoint = url.path();
-        // make method uppercase
+    /// Whether a rule allows `method` on `endpoint`. URLs that can't be
+    /// parsed or have no host are denied.
+    pub fn can_access(&self, method: &str, endpoint: &str) -> bool {
+        let Ok(url) = Url::parse(endpoint) else {
+            return false;
+        };
         let method = method.to_uppercase();
-        for rule in &self.allowed_rules {
-            if rule.method_pattern.is_match(&method) {
-                if rule.domain_pattern.is_match(domain){
-
-                
-                    if rule.endpoint_pattern.is_match(endpoint) {
-                        return true;
-                    } else {
-
-                        eprintln!("Invalid endpoint {}", domain);
-                    }
-                    
-                } else {
-
-                    eprintln!("Invalid domain {}", domain);
-                }
-            } else {
-                eprintln!("Invalid method {}", method);
-            }
-        }
-        false
+        self.allowed_rules.iter().any(|rule| rule.matches(&method, &url))
+    }
+
+    /// The limiters of the rules allowing `method` on `endpoint`, every one
+    /// of them must let the request through.
+    pub(super) fn limiters(&self, method: &str, endpoint: &str) -> Vec<Limiter> {
+        let Ok(url) = Url::parse(endpoint) else {
+            return Vec::new();
+        };
+        let method = method.to_uppercase();
+        self.allowed_rules
+            .iter()
+            .filter(|rule| rule.limiter.is_active() && rule.matches(&method, &url))
+            .map(|rule| rule.limiter.clone())
+            .collect()
     }
 }
 
 
 #[cfg(test)]
-mod tests{
-    // #[test]
-    pub fn test(){
-            let mut permissions = PermissionLayer::new();
+mod tests {
+    use super::{HTTPConfig, HttpAccessRule};
+
+    #[test]
+    fn test_can_access() {
+        let mut permissions = HTTPConfig::new();
 
         permissions.allow_access("GET", r"\w+\.example\.com", r"/api/.*");
 
-        assert_eq!(permissions.can_access("GET", "sub.example.com/api/data"), true);
-        assert_eq!(permissions.can_access("POST", "sub.example.com/api/data"), false);
-        assert_eq!(permissions.can_access("GET", "notexample.com/api/data"), false);
-        assert_eq!(permissions.can_access("GET", "sub.example.com/notapi/data"), false);
+        assert!(permissions.can_access("GET", "https://sub.example.com/api/data"));
+        assert!(permissions.can_access("get", "https://SUB.example.com/api/data"));
+        assert!(!permissions.can_access("POST", "https://sub.example.com/api/data"));
+        assert!(!permissions.can_access("GET", "https://notexample.com/api/data"));
+        assert!(!permissions.can_access("GET", "https://sub.example.com/notapi/data"));
+        assert!(!permissions.can_access("GET", "https://sub.example.com.attacker/api/data"));
+        assert!(!permissions.can_access("GET", "https://sub.example.com/v1/api/data"));
+        assert!(!permissions.can_access("GET", "sub.example.com/api/data"));
+        assert!(!permissions.can_access("GET", "not a url"));
+        assert!(!permissions.can_access("GET", "https://127.0.0.1/api/data"));
+    }
+
+    #[test]
+    fn test_scheme_and_port() {
+        let mut permissions = HTTPConfig::new();
+        permissions.add_rule(HttpAccessRule::new("GET", "example.com", ".*").unwrap().with_scheme("HTTPS"));
+        permissions.add_rule(HttpAccessRule::new("GET", "api.test", ".*").unwrap().with_port(8080));
+
+        assert!(permissions.can_access("GET", "https://example.com/"));
+        assert!(!permissions.can_access("GET", "http://example.com/"));
+        assert!(permissions.can_access("GET", "http://api.test:8080/"));
+        assert!(!permissions.can_access("GET", "http://api.test/"));
+        assert!(!permissions.can_access("GET", "https://api.test:8443/"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_ip_hosts() {
+        let mut permissions = HTTPConfig::new();
+        permissions.allow_access("GET", "10.0.0.0/8", ".*");
+        permissions.allow_access("GET", "::1", ".*");
+        permissions.allow_access("GET", "fd00::/8", ".*");
+
+        assert!(permissions.can_access("GET", "http://10.1.2.3/"));
+        assert!(permissions.can_access("GET", "http://[::ffff:10.1.2.3]/"));
+        assert!(!permissions.can_access("GET", "http://11.0.0.1/"));
+        assert!(permissions.can_access("GET", "http://[::1]:8080/"));
+        assert!(permissions.can_access("GET", "http://[fd12::1]/"));
+        assert!(!permissions.can_access("GET", "http://[fe80::1]/"));
+        // Networks don't match domain names
+        assert!(!permissions.can_access("GET", "http://10.0.0.1.example.com/"));
+
+        assert!(HttpAccessRule::new("GET", "10.0.0.0/33", ".*").is_some_and(|rule| {
+            // Not a valid network, so it is a regex that only matches itself
+            let mut config = HTTPConfig::new();
+            config.add_rule(rule);
+            !config.can_access("GET", "http://10.0.0.1/")
+        }));
+        assert!(HttpAccessRule::new("GET", "(", ".*").is_none());
+    }
+}
@@ -0,0 +1,93 @@
+//! Typed errors for `Node.HTTP.request` and `fetch`.
+//!
+//! Errors are thrown from the native callback as `CODE: message`, and
+//! `http.js` turns them back into `HttpError` objects carrying `code`.
+use std::fmt;
+
+use javy::quickjs::JSError;
+
+/// The access was denied by [`super::HTTPConfig`].
+pub const ACCESS_DENIED: &str = "EACCES";
+/// The request took longer than [`super::config::HttpLimits::timeout_ms`].
+pub const TIMEOUT: &str = "ETIMEDOUT";
+pub const REQUEST_TOO_LARGE: &str = "ERR_HTTP_REQUEST_TOO_LARGE";
+pub const RESPONSE_TOO_LARGE: &str = "ERR_HTTP_RESPONSE_TOO_LARGE";
+/// A redirect was refused by the redirect policy.
+pub const REDIRECT_DENIED: &str = "ERR_HTTP_REDIRECT_DENIED";
+pub const TOO_MANY_REDIRECTS: &str = "ERR_HTTP_TOO_MANY_REDIRECTS";
+/// A header policy forbids a header set by JS.
+pub const HEADER_FORBIDDEN: &str = "ERR_HTTP_HEADER_FORBIDDEN";
+/// The value of an injected header could not be resolved.
+pub const HEADER_UNAVAILABLE: &str = "ERR_HTTP_HEADER_UNAVAILABLE";
+/// Replay found no recorded response for the request.
+pub const NO_RECORDING: &str = "ERR_HTTP_NO_RECORDING";
+/// A rule's rate limit or cap on requests in flight was reached.
+pub const RATE_LIMITED: &str = "ERR_HTTP_RATE_LIMITED";
+/// The host could not make the request.
+pub const REQUEST_FAILED: &str = "ERR_HTTP_REQUEST_FAILED";
+
+const CODES: [&str; 11] = [
+    ACCESS_DENIED,
+    TIMEOUT,
+    REQUEST_TOO_LARGE,
+    RESPONSE_TOO_LARGE,
+    REDIRECT_DENIED,
+    TOO_MANY_REDIRECTS,
+    HEADER_FORBIDDEN,
+    HEADER_UNAVAILABLE,
+    NO_RECORDING,
+    RATE_LIMITED,
+    REQUEST_FAILED,
+];
+
+#[derive(Debug)]
+pub struct HttpError {
+    code: &'static str,
+    message: String,
+}
+
+impl HttpError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Error reported by the host, whose code is kept when it is one of ours.
+    pub(super) fn from_host(code: Option<&str>, message: &str) -> Self {
+        let code = CODES
+            .into_iter()
+            .find(|known| Some(*known) == code)
+            .unwrap_or(REQUEST_FAILED);
+        Self::new(code, format!("Request failed: {}", message))
+    }
+
+    pub fn access_denied(method: &str, url: &str) -> Self {
+        Self::new(ACCESS_DENIED, format!("Access denied: {} {}", method, url))
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    #[cfg(any(test, all(target_arch = "wasm32", feature = "http-wasi")))]
+    pub(super) fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+// Not implementing `std::error::Error` keeps this from overlapping with
+// anyhow's blanket conversion.
+impl From<HttpError> for anyhow::Error {
+    fn from(err: HttpError) -> Self {
+        // `wrap_callback` only keeps the message verbatim for `JSError`s
+        JSError::Internal(err.to_string()).into()
+    }
+}
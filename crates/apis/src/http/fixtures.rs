@@ -0,0 +1,198 @@
+//! Record and replay of the requests sent to the host, so JS calling the
+//! network can be tested without it.
+//!
+//! Fixture files have one JSON entry per line: the method and URL of the
+//! request, the response head of the host as described in [`super::host`],
+//! and the response body as `body` when it is UTF-8 text, as `body_bytes`
+//! otherwise. Recording appends to the file. Replaying never calls the host,
+//! the entries of a method and URL are served in the order they were
+//! recorded and the last one again once all were.
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use super::config::HttpMode;
+use super::error::{self, HttpError};
+use super::host::{self, Request, Response};
+
+#[derive(Debug)]
+struct Entry {
+    method: String,
+    url: String,
+    response: Value,
+    body: Vec<u8>,
+    served: bool,
+}
+
+impl Entry {
+    fn matches(&self, request: &Request) -> bool {
+        self.method == request.method && self.url == request.url
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Fixtures {
+    mode: HttpMode,
+    // Read on the first replayed request, shared by the clones of the config
+    entries: Rc<RefCell<Option<Vec<Entry>>>>,
+}
+
+impl Fixtures {
+    pub(super) fn new(mode: HttpMode) -> Self {
+        Self {
+            mode,
+            entries: Rc::default(),
+        }
+    }
+
+    pub(super) fn mode(&self) -> &HttpMode {
+        &self.mode
+    }
+
+    /// Gets the response to `request` from the host or the fixture file,
+    /// depending on the mode.
+    pub(super) fn send(&self, request: &Request) -> Result<Response> {
+        match &self.mode {
+            HttpMode::Live => host::send(request),
+            HttpMode::Record(path) => {
+                let encoded = host::call_host(request)?;
+                let (head, body) = host::unframe(&encoded)?;
+                record(path, request, head, body)
+                    .with_context(|| format!("Could not record to {}", path.display()))?;
+                Response::decode(&encoded)
+            }
+            HttpMode::Replay(path) => {
+                let mut loaded = self.entries.borrow_mut();
+                if loaded.is_none() {
+                    *loaded = Some(load(path)?);
+                }
+                let entries = loaded.as_mut().expect("fixtures are loaded");
+                let index = entries
+                    .iter()
+                    .position(|entry| entry.matches(request) && !entry.served)
+                    .or_else(|| entries.iter().rposition(|entry| entry.matches(request)))
+                    .ok_or_else(|| {
+                        HttpError::new(
+                            error::NO_RECORDING,
+                            format!("No recorded response for {} {}", request.method, request.url),
+                        )
+                    })?;
+                let entry = &mut entries[index];
+                entry.served = true;
+                Response::decode(&host::frame(&entry.response, &entry.body))
+            }
+        }
+    }
+}
+
+fn record(path: &Path, request: &Request, head: Value, body: &[u8]) -> Result<()> {
+    let mut entry = json!({
+        "method": request.method,
+        "url": request.url,
+        "response": head,
+    });
+    match std::str::from_utf8(body) {
+        Ok(text) => entry["body"] = text.into(),
+        Err(_) => entry["body_bytes"] = body.into(),
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry)?;
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Vec<Entry>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Could not read HTTP fixtures {}", path.display()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            parse_entry(line)
+                .ok_or_else(|| anyhow!("Invalid HTTP fixture at {}:{}", path.display(), number + 1))
+        })
+        .collect()
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let entry: Value = serde_json::from_str(line).ok()?;
+    let body = if let Some(text) = entry.get("body").and_then(Value::as_str) {
+        text.as_bytes().to_vec()
+    } else if let Some(bytes) = entry.get("body_bytes") {
+        serde_json::from_value(bytes.clone()).ok()?
+    } else {
+        Vec::new()
+    };
+    Some(Entry {
+        method: entry.get("method")?.as_str()?.to_string(),
+        url: entry.get("url")?.as_str()?.to_string(),
+        response: entry.get("response")?.clone(),
+        body,
+        served: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::config::HttpMode;
+    use crate::http::host::{stand_in, Request, Response};
+    use super::Fixtures;
+
+    fn request(method: &str, url: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let path = "./test_http_fixtures.jsonl";
+        let _ = std::fs::remove_file(path);
+
+        let mut count = 0;
+        stand_in::serve(move |request| {
+            count += 1;
+            let body = if request.url.ends_with("/bin") {
+                vec![0xff, 0]
+            } else {
+                format!("{} #{}", request.url, count).into_bytes()
+            };
+            Ok(Response {
+                status: 200,
+                headers: vec![("content-type".to_string(), "text/plain".to_string())],
+                body,
+            })
+        });
+        let recorder = Fixtures::new(HttpMode::Record(path.into()));
+        for url in ["http://a.test/x", "http://a.test/x", "http://a.test/bin"] {
+            recorder.send(&request("GET", url)).unwrap();
+        }
+
+        stand_in::serve(|_| panic!("the host was called"));
+        let replayer = Fixtures::new(HttpMode::Replay(path.into()));
+        let mut bodies = Vec::new();
+        for url in ["http://a.test/x", "http://a.test/x", "http://a.test/x", "http://a.test/bin"] {
+            let response = replayer.send(&request("GET", url)).unwrap();
+            assert_eq!(response.status, 200);
+            assert_eq!(response.headers[0].1, "text/plain");
+            bodies.push(response.body);
+        }
+        assert_eq!(bodies[0], b"http://a.test/x #1");
+        assert_eq!(bodies[1], b"http://a.test/x #2");
+        assert_eq!(bodies[2], b"http://a.test/x #2");
+        assert_eq!(bodies[3], [0xff, 0]);
+
+        let error = replayer.send(&request("POST", "http://a.test/x")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ERR_HTTP_NO_RECORDING: No recorded response for POST http://a.test/x"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+//! Header policies applied to the requests of JS before they reach the host.
+//!
+//! Injected values are resolved here when each request is made, including
+//! every redirect, so secrets like bearer tokens are never handed to JS:
+//! they are not in the headers of `Request` objects nor in error messages.
+use anyhow::Result;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use url::Url;
+
+use super::config::UrlPattern;
+use super::error::{self, HttpError};
+use super::host::Request;
+
+/// A piece of an injected header value.
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    /// `${env:NAME}`, an environment variable of the module.
+    Env(String),
+    /// `${file:PATH}`, a file in a directory preopened by the host, without
+    /// its trailing newline.
+    File(PathBuf),
+}
+
+/// Parses values like `Bearer ${env:API_TOKEN}`, returns `None` for an
+/// unterminated or unknown placeholder.
+fn parse_template(template: &str) -> Option<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let end = start + rest[start..].find('}')?;
+        let (kind, name) = rest[start + 2..end].split_once(':')?;
+        parts.push(match kind {
+            "env" => Part::Env(name.to_string()),
+            "file" => Part::File(PathBuf::from(name)),
+            _ => return None,
+        });
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    Some(parts)
+}
+
+/// Looks up the values of the `${env:NAME}` and `${file:PATH}`
+/// placeholders, `None` when a value is missing.
+pub trait SecretSource: Debug {
+    fn env(&self, name: &str) -> Option<String>;
+    fn file(&self, path: &Path) -> Option<String>;
+}
+
+/// The environment variables and preopened directories of the module.
+#[derive(Debug)]
+struct HostSecrets;
+
+impl SecretSource for HostSecrets {
+    fn env(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+
+    fn file(&self, path: &Path) -> Option<String> {
+        std::fs::read_to_string(path).ok()
+    }
+}
+
+fn resolve(parts: &[Part], source: &dyn SecretSource) -> Option<String> {
+    let mut value = String::new();
+    for part in parts {
+        match part {
+            Part::Text(text) => value.push_str(text),
+            Part::Env(name) => value.push_str(&source.env(name)?),
+            Part::File(path) => {
+                let content = source.file(path)?;
+                value.push_str(content.trim_end_matches(['\r', '\n']));
+            }
+        }
+    }
+    // A value can't add headers of its own
+    if value.contains(['\r', '\n', '\0']) {
+        return None;
+    }
+    Some(value)
+}
+
+#[derive(Clone, Debug)]
+pub struct HeaderPolicy {
+    pattern: UrlPattern,
+    inject: Vec<(String, Vec<Part>)>,
+    strip: Vec<String>,
+    protect: Vec<String>,
+    source: Rc<dyn SecretSource>,
+}
+
+impl HeaderPolicy {
+    /// Applies to the URLs matched by the domain and endpoint patterns, like
+    /// [`super::config::HttpAccessRule::new`]. Returns `None` when one of
+    /// the patterns is not a valid regex.
+    pub fn new(domain_pattern: &str, endpoint_pattern: &str) -> Option<Self> {
+        Some(HeaderPolicy {
+            pattern: UrlPattern::new(domain_pattern, endpoint_pattern)?,
+            inject: Vec::new(),
+            strip: Vec::new(),
+            protect: Vec::new(),
+            source: Rc::new(HostSecrets),
+        })
+    }
+
+    /// Sends the header `name` with `value`, in place of the one of JS.
+    /// `${env:NAME}` and `${file:PATH}` in `value` are replaced when the
+    /// request is made. Returns `None` when `value` has an invalid
+    /// placeholder.
+    pub fn with_injected(mut self, name: &str, value: &str) -> Option<Self> {
+        self.inject.push((name.to_ascii_lowercase(), parse_template(value)?));
+        Some(self)
+    }
+
+    /// Removes the header `name` set by JS.
+    pub fn with_stripped(mut self, name: &str) -> Self {
+        self.strip.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Rejects requests where JS sets the header `name`.
+    pub fn with_protected(mut self, name: &str) -> Self {
+        self.protect.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Looks up the placeholders of the injected values in `source`
+    /// instead of the environment and files of the module.
+    pub fn with_source(mut self, source: Rc<dyn SecretSource>) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+/// The request sent for `request` once the policies matching its URL are
+/// applied.
+pub(super) fn apply(policies: &[HeaderPolicy], request: &Request) -> Result<Request> {
+    let mut outgoing = request.clone();
+    let Ok(url) = Url::parse(&request.url) else {
+        return Ok(outgoing);
+    };
+    for policy in policies.iter().filter(|policy| policy.pattern.matches(&url)) {
+        if let Some((name, _)) = request
+            .headers
+            .iter()
+            .find(|(name, _)| policy.protect.contains(&name.to_ascii_lowercase()))
+        {
+            return Err(HttpError::new(
+                error::HEADER_FORBIDDEN,
+                format!("The {} header can't be set for {}", name, request.url),
+            )
+            .into());
+        }
+        outgoing
+            .headers
+            .retain(|(name, _)| !policy.strip.contains(&name.to_ascii_lowercase()));
+        for (name, parts) in &policy.inject {
+            let value = resolve(parts, policy.source.as_ref()).ok_or_else(|| {
+                HttpError::new(
+                    error::HEADER_UNAVAILABLE,
+                    format!("Could not resolve the {} header for {}", name, request.url),
+                )
+            })?;
+            outgoing.headers.retain(|(other, _)| !other.eq_ignore_ascii_case(name));
+            outgoing.headers.push((name.clone(), value));
+        }
+    }
+    Ok(outgoing)
+}
@@ -0,0 +1,273 @@
+//! The host side of `Node.HTTP.request`.
+//!
+//! In Wasm, requests are handed to the host through the `javy_http_v1` import
+//! module, so the embedder decides how (and whether) they reach the network.
+//! Both directions use the same framing: a little-endian `u32` with the
+//! length of a JSON head, the head, then the raw body.
+//!
+//! - request head: `{"method": "GET", "url": "...", "headers": [["name", "value"], ...],
+//!   "timeout_ms": 5000, "max_response_bytes": 1048576}`, the limits being
+//!   `null` when there is none
+//! - response head: `{"status": 200, "headers": [["name", "value"], ...]}`,
+//!   or `{"error": "message", "code": "ETIMEDOUT"}` when the request could
+//!   not be made, `code` being optional
+//!
+//! The host gives up on requests over `timeout_ms` with the code `ETIMEDOUT`
+//! and on bodies over `max_response_bytes` with
+//! `ERR_HTTP_RESPONSE_TOO_LARGE`. It must not follow redirects, they are
+//! followed here so every target goes through the access rules.
+//!
+//! With the `http-wasi` feature, requests go through `wasi:http` instead,
+//! see [`super::wasi_http`].
+//!
+//! [`super::fixtures`] can record the responses or replay them instead of
+//! calling the host, and the tests replace the host with [`stand_in`],
+//! which answers requests in-process.
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+
+use super::error::HttpError;
+
+#[cfg(all(target_arch = "wasm32", not(feature = "http-wasi"), not(test)))]
+#[link(wasm_import_module = "javy_http_v1")]
+extern "C" {
+    /// Sends the encoded request, returns the length of the encoded response
+    /// the host keeps until `response` is called.
+    fn request(data: *const u8, length: usize) -> usize;
+
+    /// Copies the pending response in `data`, which has the length returned
+    /// by `request`.
+    fn response(data: *mut u8, length: usize);
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Request {
+    pub(super) method: String,
+    pub(super) url: String,
+    pub(super) headers: Vec<(String, String)>,
+    pub(super) body: Vec<u8>,
+    pub(super) timeout_ms: Option<u64>,
+    pub(super) max_response_bytes: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Response {
+    pub(super) status: u16,
+    pub(super) headers: Vec<(String, String)>,
+    pub(super) body: Vec<u8>,
+}
+
+/// Sends `request` to the host and waits for its response.
+pub(super) fn send(request: &Request) -> Result<Response> {
+    let encoded = call_host(request)?;
+    Response::decode(&encoded)
+}
+
+/// Returns the encoded response to `request`.
+#[cfg(all(target_arch = "wasm32", not(feature = "http-wasi"), not(test)))]
+pub(super) fn call_host(request: &Request) -> Result<Vec<u8>> {
+    let encoded = request.encode();
+    let length = unsafe { self::request(encoded.as_ptr(), encoded.len()) };
+    let mut buffer = vec![0; length];
+    unsafe { response(buffer.as_mut_ptr(), length) };
+    Ok(buffer)
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "http-wasi", not(test)))]
+pub(super) fn call_host(request: &Request) -> Result<Vec<u8>> {
+    // Encoded like the responses of the host import, so recording and the
+    // error codes work the same
+    Ok(match super::wasi_http::send(request) {
+        Ok(response) => response.encode(),
+        Err(e) => encode_error(Some(e.code()), e.message()),
+    })
+}
+
+#[cfg(all(not(target_arch = "wasm32"), not(test)))]
+pub(super) fn call_host(_request: &Request) -> Result<Vec<u8>> {
+    bail!("HTTP requests need the javy_http_v1 host functions")
+}
+
+#[cfg(test)]
+pub(super) fn call_host(request: &Request) -> Result<Vec<u8>> {
+    Ok(stand_in::handle(&request.encode()))
+}
+
+impl Request {
+    #[cfg(any(test, all(target_arch = "wasm32", not(feature = "http-wasi"))))]
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let head = json!({
+            "method": self.method,
+            "url": self.url,
+            "headers": self.headers,
+            "timeout_ms": self.timeout_ms,
+            "max_response_bytes": self.max_response_bytes,
+        });
+        frame(&head, &self.body)
+    }
+
+    #[cfg(test)]
+    pub(super) fn decode(data: &[u8]) -> Result<Self> {
+        let (head, body) = unframe(data)?;
+        Ok(Self {
+            method: string_field(&head, "method")?,
+            url: string_field(&head, "url")?,
+            headers: headers_field(&head)?,
+            body: body.to_vec(),
+            timeout_ms: head.get("timeout_ms").and_then(Value::as_u64),
+            max_response_bytes: head.get("max_response_bytes").and_then(Value::as_u64),
+        })
+    }
+}
+
+impl Response {
+    #[cfg(any(test, all(target_arch = "wasm32", feature = "http-wasi")))]
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let head = json!({
+            "status": self.status,
+            "headers": self.headers,
+        });
+        frame(&head, &self.body)
+    }
+
+    /// Fails with the host's message when it reported an error.
+    pub(super) fn decode(data: &[u8]) -> Result<Self> {
+        let (head, body) = unframe(data)?;
+        if let Some(error) = head.get("error") {
+            let code = head.get("code").and_then(Value::as_str);
+            return Err(HttpError::from_host(code, error.as_str().unwrap_or_default()).into());
+        }
+        let status = head
+            .get("status")
+            .and_then(Value::as_u64)
+            .and_then(|status| u16::try_from(status).ok())
+            .ok_or_else(|| anyhow!("Invalid response status"))?;
+        Ok(Self {
+            status,
+            headers: headers_field(&head)?,
+            body: body.to_vec(),
+        })
+    }
+}
+
+/// Encodes the response of a request that could not be made.
+#[cfg(any(test, all(target_arch = "wasm32", feature = "http-wasi")))]
+pub(super) fn encode_error(code: Option<&str>, message: &str) -> Vec<u8> {
+    frame(&json!({ "error": message, "code": code }), &[])
+}
+
+pub(super) fn frame(head: &Value, body: &[u8]) -> Vec<u8> {
+    let head = head.to_string();
+    let mut data = Vec::with_capacity(4 + head.len() + body.len());
+    data.extend_from_slice(&(head.len() as u32).to_le_bytes());
+    data.extend_from_slice(head.as_bytes());
+    data.extend_from_slice(body);
+    data
+}
+
+pub(super) fn unframe(data: &[u8]) -> Result<(Value, &[u8])> {
+    if data.len() < 4 {
+        bail!("Truncated HTTP message");
+    }
+    let (length, rest) = data.split_at(4);
+    let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    if rest.len() < length {
+        bail!("Truncated HTTP message");
+    }
+    let (head, body) = rest.split_at(length);
+    Ok((serde_json::from_slice(head)?, body))
+}
+
+#[cfg(test)]
+fn string_field(head: &Value, name: &str) -> Result<String> {
+    head.get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Missing {} in HTTP message", name))
+}
+
+fn headers_field(head: &Value) -> Result<Vec<(String, String)>> {
+    let Some(headers) = head.get("headers") else {
+        return Ok(Vec::new());
+    };
+    serde_json::from_value(headers.clone()).map_err(|_| anyhow!("Invalid headers in HTTP message"))
+}
+
+/// In-process host for the tests: requests go through the same encoding as
+/// in Wasm and are answered by the handler given to [`stand_in::serve`].
+#[cfg(test)]
+pub(super) mod stand_in {
+    use std::cell::RefCell;
+
+    use super::{encode_error, HttpError, Request, Response};
+
+    type Handler = Box<dyn FnMut(Request) -> Result<Response, HttpError>>;
+
+    thread_local! {
+        static HANDLER: RefCell<Option<Handler>> = RefCell::new(None);
+    }
+
+    /// Answers the requests of the current thread with `handler`, errors
+    /// are reported the way the host reports requests it could not make.
+    pub(in crate::http) fn serve(handler: impl FnMut(Request) -> Result<Response, HttpError> + 'static) {
+        HANDLER.with(|h| *h.borrow_mut() = Some(Box::new(handler)));
+    }
+
+    pub(super) fn handle(encoded: &[u8]) -> Vec<u8> {
+        let request = match Request::decode(encoded) {
+            Ok(request) => request,
+            Err(e) => return encode_error(None, &e.to_string()),
+        };
+        HANDLER.with(|h| match h.borrow_mut().as_mut() {
+            Some(handler) => match handler(request) {
+                Ok(response) => response.encode(),
+                Err(e) => encode_error(Some(e.code()), e.message()),
+            },
+            None => encode_error(None, "connection refused"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_error, Request, Response};
+
+    #[test]
+    fn test_wire_format() {
+        let request = Request {
+            method: "POST".to_string(),
+            url: "http://example.com/a".to_string(),
+            headers: vec![("content-type".to_string(), "text/plain".to_string())],
+            body: b"ping".to_vec(),
+            timeout_ms: Some(500),
+            max_response_bytes: None,
+        };
+        let decoded = Request::decode(&request.encode()).unwrap();
+        assert_eq!(decoded.method, "POST");
+        assert_eq!(decoded.url, "http://example.com/a");
+        assert_eq!(decoded.headers, request.headers);
+        assert_eq!(decoded.body, b"ping");
+        assert_eq!(decoded.timeout_ms, Some(500));
+        assert_eq!(decoded.max_response_bytes, None);
+
+        let response = Response {
+            status: 404,
+            headers: vec![],
+            body: b"\xffpong".to_vec(),
+        };
+        let decoded = Response::decode(&response.encode()).unwrap();
+        assert_eq!(decoded.status, 404);
+        assert_eq!(decoded.body, b"\xffpong");
+
+        let error = Response::decode(&encode_error(None, "refused")).unwrap_err();
+        assert_eq!(error.to_string(), "ERR_HTTP_REQUEST_FAILED: Request failed: refused");
+        let error = Response::decode(&encode_error(Some("ETIMEDOUT"), "timed out")).unwrap_err();
+        assert_eq!(error.to_string(), "ETIMEDOUT: Request failed: timed out");
+        // A host limiting requests of its own reports them as rate limited
+        let error = Response::decode(&encode_error(Some("ERR_HTTP_RATE_LIMITED"), "slow down")).unwrap_err();
+        assert_eq!(error.to_string(), "ERR_HTTP_RATE_LIMITED: Request failed: slow down");
+        let error = Response::decode(&encode_error(Some("EWHATEVER"), "?")).unwrap_err();
+        assert_eq!(error.to_string(), "ERR_HTTP_REQUEST_FAILED: Request failed: ?");
+        assert!(Response::decode(&[1, 0]).is_err());
+    }
+}
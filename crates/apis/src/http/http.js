@@ -1,17 +1,599 @@
 (function () {
-    const __request = globalThis.__request;
+    const HTTP_ERROR_MESSAGE = /^(E[A-Z]+|ERR_[A-Z_]+): (.*)$/;
+
+    // Denials, limits and failed requests, a TypeError like the errors of
+    // fetch with the reason in `code`
+    class HttpError extends TypeError {
+        constructor(message, code) {
+            super(message);
+            this.name = "HttpError";
+            this.code = code;
+        }
+    }
+
+    function withHttpErrors(native) {
+        return function (...args) {
+            try {
+                return native(...args);
+            } catch (e) {
+                const match = HTTP_ERROR_MESSAGE.exec(e.message);
+                if (match === null) {
+                    throw e;
+                }
+                throw new HttpError(match[2], match[1]);
+            }
+        };
+    }
+
+    const __request = withHttpErrors(globalThis.__request);
+    const __httpDecodeUtf8 = globalThis.__httpDecodeUtf8;
+    const __httpEncodeUtf8 = globalThis.__httpEncodeUtf8;
+
+    // Strings are sent as UTF-8, views and buffers as their bytes
+    function requestBody(data) {
+        if (data === undefined || data === null || typeof data === "string") {
+            return data;
+        }
+        if (data instanceof ArrayBuffer) {
+            return data;
+        }
+        if (ArrayBuffer.isView(data)) {
+            return data.buffer.slice(data.byteOffset, data.byteOffset + data.byteLength);
+        }
+        return String(data);
+    }
+
+    function requestHeaders(headers) {
+        const result = {};
+        for (const [name, value] of Object.entries(headers || {})) {
+            result[name] = String(value);
+        }
+        return result;
+    }
 
     globalThis.Node.HTTP = {
+        // Returns `{ status, headers, body, url, redirected }`, the body
+        // being an ArrayBuffer and `url` the one of the last redirect
         request(endpoint, data, headers, method){
             return __request(
-                endpoint,
-                data,
-                headers,
-                method
+                String(endpoint),
+                requestBody(data),
+                requestHeaders(headers),
+                method === undefined ? "GET" : String(method),
+                "follow"
             );
         },
+        HttpError,
     };
-  
+
+    // WHATWG fetch on top of the same host call. Bodies are kept whole in
+    // memory as a string or an ArrayBuffer, or given as an async iterable of
+    // chunks like in Node.js, ReadableStream is not supported.
+
+    const INTERNAL = Symbol("internal");
+    const CONSUME = Symbol("consume");
+    const GUARD = Symbol("guard");
+    const TOKEN = /^[!#$%&'*+\-.^_`|~0-9A-Za-z]+$/;
+    const NULL_BODY_STATUSES = [101, 103, 204, 205, 304];
+    const REDIRECT_STATUSES = [301, 302, 303, 307, 308];
+    const FORBIDDEN_METHODS = ["CONNECT", "TRACE", "TRACK"];
+    const NORMALIZED_METHODS = ["DELETE", "GET", "HEAD", "OPTIONS", "POST", "PUT"];
+    const REDIRECT_MODES = ["follow", "error", "manual"];
+
+    function normalizeName(name) {
+        name = String(name);
+        if (!TOKEN.test(name)) {
+            throw new TypeError(`Invalid header name: ${name}`);
+        }
+        return name.toLowerCase();
+    }
+
+    function normalizeValue(value) {
+        value = String(value).replace(/^[\t\n\r ]+|[\t\n\r ]+$/g, "");
+        if (/[\0\r\n]|[^\x00-\xff]/.test(value)) {
+            throw new TypeError(`Invalid header value: ${value}`);
+        }
+        return value;
+    }
+
+    // Sorted by name with repeated headers combined, except Set-Cookie
+    function sortAndCombine(headers) {
+        const names = [...new Set(headers[INTERNAL].map(([n]) => n))].sort();
+        const pairs = [];
+        for (const name of names) {
+            if (name === "set-cookie") {
+                for (const value of headers.getSetCookie()) {
+                    pairs.push([name, value]);
+                }
+            } else {
+                pairs.push([name, headers.get(name)]);
+            }
+        }
+        return pairs;
+    }
+
+    // Pairs are sorted again on every step, so changes made while iterating
+    // are seen like in browsers
+    const HeadersIteratorPrototype = Object.create(Object.getPrototypeOf(Object.getPrototypeOf([][Symbol.iterator]())), {
+        next: {
+            value: function next() {
+                const state = this[INTERNAL];
+                const pairs = sortAndCombine(state.headers);
+                if (state.index >= pairs.length) {
+                    return { value: undefined, done: true };
+                }
+                const [name, value] = pairs[state.index++];
+                const result = state.kind === "key" ? name : state.kind === "value" ? value : [name, value];
+                return { value: result, done: false };
+            },
+            configurable: true,
+            enumerable: true,
+            writable: true,
+        },
+        [Symbol.toStringTag]: { value: "Headers Iterator", configurable: true },
+    });
+
+    function headersIterator(headers, kind) {
+        const iterator = Object.create(HeadersIteratorPrototype);
+        iterator[INTERNAL] = { headers, kind, index: 0 };
+        return iterator;
+    }
+
+    function checkMutable(headers) {
+        if (headers[GUARD] === "immutable") {
+            throw new TypeError("Headers are immutable");
+        }
+    }
+
+    // Headers of responses from `fetch` and `Response.error` can't be changed
+    function immutableHeaders(init) {
+        const headers = new Headers(init);
+        headers[GUARD] = "immutable";
+        return headers;
+    }
+
+    class Headers {
+        constructor(init) {
+            this[INTERNAL] = [];
+            this[GUARD] = "none";
+            if (init === undefined) {
+                return;
+            }
+            if (typeof init !== "object" && typeof init !== "function") {
+                throw new TypeError("Headers must be constructed from an object or iterable");
+            }
+            if (typeof init[Symbol.iterator] === "function") {
+                for (const pair of init) {
+                    const entry = Array.from(pair);
+                    if (entry.length !== 2) {
+                        throw new TypeError("Header pairs must have exactly two items");
+                    }
+                    this.append(entry[0], entry[1]);
+                }
+            } else {
+                for (const name of Object.keys(init)) {
+                    this.append(name, init[name]);
+                }
+            }
+        }
+
+        append(name, value) {
+            checkMutable(this);
+            this[INTERNAL].push([normalizeName(name), normalizeValue(value)]);
+        }
+
+        delete(name) {
+            name = normalizeName(name);
+            checkMutable(this);
+            this[INTERNAL] = this[INTERNAL].filter(([n]) => n !== name);
+        }
+
+        get(name) {
+            name = normalizeName(name);
+            const values = this[INTERNAL].filter(([n]) => n === name).map(([, v]) => v);
+            return values.length === 0 ? null : values.join(", ");
+        }
+
+        getSetCookie() {
+            return this[INTERNAL].filter(([n]) => n === "set-cookie").map(([, v]) => v);
+        }
+
+        has(name) {
+            name = normalizeName(name);
+            return this[INTERNAL].some(([n]) => n === name);
+        }
+
+        set(name, value) {
+            name = normalizeName(name);
+            value = normalizeValue(value);
+            checkMutable(this);
+            const list = this[INTERNAL];
+            const index = list.findIndex(([n]) => n === name);
+            if (index === -1) {
+                list.push([name, value]);
+            } else {
+                list[index][1] = value;
+                this[INTERNAL] = list.filter(([n], i) => n !== name || i === index);
+            }
+        }
+
+        forEach(callback, thisArg) {
+            if (typeof callback !== "function") {
+                throw new TypeError("Headers.forEach callback must be a function");
+            }
+            for (const [name, value] of this) {
+                callback.call(thisArg, value, name, this);
+            }
+        }
+
+        entries() {
+            return headersIterator(this, "key+value");
+        }
+
+        keys() {
+            return headersIterator(this, "key");
+        }
+
+        values() {
+            return headersIterator(this, "value");
+        }
+
+        get [Symbol.toStringTag]() {
+            return "Headers";
+        }
+    }
+    Headers.prototype[Symbol.iterator] = Headers.prototype.entries;
+
+    function isAsyncIterable(body) {
+        return typeof body === "object" && body !== null && typeof body[Symbol.asyncIterator] === "function";
+    }
+
+    function chunkBytes(chunk) {
+        if (typeof chunk === "string") {
+            return new Uint8Array(__httpEncodeUtf8(chunk));
+        }
+        if (chunk instanceof ArrayBuffer) {
+            return new Uint8Array(chunk);
+        }
+        if (ArrayBuffer.isView(chunk)) {
+            return new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength);
+        }
+        throw new TypeError("Body chunks must be strings, ArrayBuffers or views");
+    }
+
+    async function readAll(chunks) {
+        const parts = [];
+        let length = 0;
+        for await (const chunk of chunks) {
+            const bytes = chunkBytes(chunk);
+            parts.push(bytes);
+            length += bytes.byteLength;
+        }
+        const result = new Uint8Array(length);
+        let offset = 0;
+        for (const part of parts) {
+            result.set(part, offset);
+            offset += part.byteLength;
+        }
+        return result.buffer;
+    }
+
+    // Iterating marks the body as used, like reading a stream
+    function bodyStream(internal) {
+        return {
+            async *[Symbol.asyncIterator]() {
+                if (internal.bodyUsed) {
+                    throw new TypeError("Body has already been consumed");
+                }
+                internal.bodyUsed = true;
+                if (isAsyncIterable(internal.body)) {
+                    for await (const chunk of internal.body) {
+                        yield chunkBytes(chunk);
+                    }
+                } else {
+                    yield chunkBytes(internal.body);
+                }
+            },
+        };
+    }
+
+    // Returns the body as a string, an ArrayBuffer or an async iterable and
+    // its default content type
+    function extractBody(body) {
+        if (body === undefined || body === null) {
+            return [null, null];
+        }
+        if (typeof body === "string") {
+            return [body, "text/plain;charset=UTF-8"];
+        }
+        if (body instanceof ArrayBuffer) {
+            return [body.slice(0), null];
+        }
+        if (ArrayBuffer.isView(body)) {
+            return [body.buffer.slice(body.byteOffset, body.byteOffset + body.byteLength), null];
+        }
+        if (isAsyncIterable(body)) {
+            return [body, null];
+        }
+        if (typeof URLSearchParams !== "undefined" && body instanceof URLSearchParams) {
+            return [String(body), "application/x-www-form-urlencoded;charset=UTF-8"];
+        }
+        return [String(body), "text/plain;charset=UTF-8"];
+    }
+
+    // Shared by Request and Response
+    class Body {
+        // An async iterable of Uint8Array chunks, not a ReadableStream
+        get body() {
+            const internal = this[INTERNAL];
+            if (internal.body === null) {
+                return null;
+            }
+            if (internal.stream === undefined) {
+                internal.stream = bodyStream(internal);
+            }
+            return internal.stream;
+        }
+
+        get bodyUsed() {
+            return this[INTERNAL].bodyUsed;
+        }
+
+        arrayBuffer() {
+            return this[CONSUME]().then((body) => {
+                if (body === null) {
+                    return new ArrayBuffer(0);
+                }
+                return typeof body === "string" ? __httpEncodeUtf8(body) : body;
+            });
+        }
+
+        text() {
+            return this[CONSUME]().then((body) => {
+                if (body === null) {
+                    return "";
+                }
+                return typeof body === "string" ? body : __httpDecodeUtf8(body);
+            });
+        }
+
+        json() {
+            return this.text().then((text) => JSON.parse(text));
+        }
+
+        [CONSUME]() {
+            const internal = this[INTERNAL];
+            if (internal.bodyUsed) {
+                return Promise.reject(new TypeError("Body has already been consumed"));
+            }
+            if (internal.body !== null) {
+                internal.bodyUsed = true;
+            }
+            return isAsyncIterable(internal.body) ? readAll(internal.body) : Promise.resolve(internal.body);
+        }
+    }
+
+    class Request extends Body {
+        constructor(input, init = {}) {
+            super();
+            const source = input instanceof Request ? input[INTERNAL] : null;
+            let method = init.method !== undefined ? String(init.method) : (source ? source.method : "GET");
+            if (!TOKEN.test(method)) {
+                throw new TypeError(`Invalid method: ${method}`);
+            }
+            if (NORMALIZED_METHODS.includes(method.toUpperCase())) {
+                method = method.toUpperCase();
+            }
+            if (FORBIDDEN_METHODS.includes(method.toUpperCase())) {
+                throw new TypeError(`Forbidden method: ${method}`);
+            }
+
+            let body = null;
+            let contentType = null;
+            if (init.body !== undefined && init.body !== null) {
+                [body, contentType] = extractBody(init.body);
+            } else if (source) {
+                if (source.bodyUsed) {
+                    throw new TypeError("Body has already been consumed");
+                }
+                // The new request takes the body over, the source can't be
+                // read or sent anymore
+                body = source.body;
+                if (body !== null) {
+                    source.bodyUsed = true;
+                }
+            }
+            if (body !== null && (method === "GET" || method === "HEAD")) {
+                throw new TypeError(`${method} requests cannot have a body`);
+            }
+
+            const redirect = init.redirect !== undefined ? String(init.redirect) : (source ? source.redirect : "follow");
+            if (!REDIRECT_MODES.includes(redirect)) {
+                throw new TypeError(`Invalid redirect mode: ${redirect}`);
+            }
+
+            const headers = new Headers(init.headers !== undefined ? init.headers : (source ? source.headers : undefined));
+            if (contentType !== null && !headers.has("content-type")) {
+                headers.set("content-type", contentType);
+            }
+
+            this[INTERNAL] = {
+                url: source ? source.url : String(input),
+                method,
+                headers,
+                body,
+                bodyUsed: false,
+                redirect,
+                signal: init.signal !== undefined ? init.signal : (source ? source.signal : null),
+            };
+        }
+
+        get url() { return this[INTERNAL].url; }
+        get method() { return this[INTERNAL].method; }
+        get headers() { return this[INTERNAL].headers; }
+        get redirect() { return this[INTERNAL].redirect; }
+        get signal() { return this[INTERNAL].signal; }
+
+        clone() {
+            if (this.bodyUsed) {
+                throw new TypeError("Body has already been consumed");
+            }
+            if (isAsyncIterable(this[INTERNAL].body)) {
+                throw new TypeError("Streamed bodies can't be cloned");
+            }
+            const request = new Request(this);
+            // Unlike `new Request(request)`, cloning leaves the body readable
+            this[INTERNAL].bodyUsed = false;
+            return request;
+        }
+
+        get [Symbol.toStringTag]() {
+            return "Request";
+        }
+    }
+
+    class Response extends Body {
+        constructor(body = null, init = {}) {
+            super();
+            const status = init.status !== undefined ? Number(init.status) : 200;
+            if (!Number.isInteger(status) || status < 200 || status > 599) {
+                throw new RangeError(`Invalid status: ${init.status}`);
+            }
+            const statusText = init.statusText !== undefined ? String(init.statusText) : "";
+            if (/[^\t\x20-\x7e\x80-\xff]/.test(statusText)) {
+                throw new TypeError(`Invalid status text: ${statusText}`);
+            }
+
+            const headers = new Headers(init.headers);
+            const [extracted, contentType] = extractBody(body);
+            if (extracted !== null && NULL_BODY_STATUSES.includes(status)) {
+                throw new TypeError(`Response with status ${status} cannot have a body`);
+            }
+            if (contentType !== null && !headers.has("content-type")) {
+                headers.set("content-type", contentType);
+            }
+
+            this[INTERNAL] = {
+                status,
+                statusText,
+                headers,
+                body: extracted,
+                bodyUsed: false,
+                type: "default",
+                url: "",
+                redirected: false,
+            };
+        }
+
+        static error() {
+            const response = new Response();
+            Object.assign(response[INTERNAL], { status: 0, type: "error", headers: immutableHeaders() });
+            return response;
+        }
+
+        static json(data, init = {}) {
+            const text = JSON.stringify(data);
+            if (text === undefined) {
+                throw new TypeError("Data is not JSON serializable");
+            }
+            const headers = new Headers(init.headers);
+            if (!headers.has("content-type")) {
+                headers.set("content-type", "application/json");
+            }
+            return new Response(text, { ...init, headers });
+        }
+
+        static redirect(url, status = 302) {
+            if (!REDIRECT_STATUSES.includes(status)) {
+                throw new RangeError(`Invalid redirect status: ${status}`);
+            }
+            return new Response(null, { status, headers: { location: String(url) } });
+        }
+
+        get status() { return this[INTERNAL].status; }
+        get statusText() { return this[INTERNAL].statusText; }
+        get ok() { return this.status >= 200 && this.status <= 299; }
+        get headers() { return this[INTERNAL].headers; }
+        get type() { return this[INTERNAL].type; }
+        get url() { return this[INTERNAL].url; }
+        get redirected() { return this[INTERNAL].redirected; }
+
+        clone() {
+            if (this.bodyUsed) {
+                throw new TypeError("Body has already been consumed");
+            }
+            const internal = this[INTERNAL];
+            if (isAsyncIterable(internal.body)) {
+                throw new TypeError("Streamed bodies can't be cloned");
+            }
+            const response = new Response();
+            const headers = new Headers(internal.headers);
+            headers[GUARD] = internal.headers[GUARD];
+            response[INTERNAL] = { ...internal, headers };
+            return response;
+        }
+
+        get [Symbol.toStringTag]() {
+            return "Response";
+        }
+    }
+
+    // Network errors, denials and limits reject with an HttpError, the other
+    // failures with a TypeError, like browsers
+    function fetch(input, init) {
+        return new Promise((resolve) => {
+            const request = new Request(input, init);
+            const body = request[INTERNAL].body;
+            // Streamed bodies are read whole before the request is sent
+            resolve(isAsyncIterable(body) ? readAll(body).then((bytes) => send(request, bytes)) : send(request, body));
+        });
+    }
+
+    // What fetch rejects with when its signal is aborted
+    function abortError(signal) {
+        if (signal.reason !== undefined) {
+            return signal.reason;
+        }
+        const error = new Error("This operation was aborted");
+        error.name = "AbortError";
+        return error;
+    }
+
+    function send(request, body) {
+        // Requests block until they complete, so an abort can only be seen
+        // before sending
+        const signal = request.signal;
+        if (signal && signal.aborted) {
+            throw abortError(signal);
+        }
+        const headers = {};
+        for (const [name, value] of request.headers) {
+            headers[name] = value;
+        }
+        let result;
+        try {
+            result = __request(request.url, body, headers, request.method, request.redirect);
+        } catch (e) {
+            throw e instanceof HttpError ? e : new TypeError(`fetch failed: ${e.message}`);
+        }
+        const response = new Response();
+        Object.assign(response[INTERNAL], {
+            status: result.status,
+            headers: immutableHeaders(Object.entries(result.headers)),
+            body: NULL_BODY_STATUSES.includes(result.status) ? null : result.body,
+            type: "basic",
+            url: result.url,
+            redirected: result.redirected,
+        });
+        return response;
+    }
+
+    globalThis.Headers = Headers;
+    globalThis.Request = Request;
+    globalThis.Response = Response;
+    globalThis.fetch = fetch;
+
     Reflect.deleteProperty(globalThis, "__request");
+    Reflect.deleteProperty(globalThis, "__httpDecodeUtf8");
+    Reflect.deleteProperty(globalThis, "__httpEncodeUtf8");
   })();
-  
\ No newline at end of file
@@ -1,17 +1,26 @@
 use anyhow::Result;
+use std::collections::HashMap;
 
-use javy::Runtime;
+use javy::{quickjs::JSValue, Runtime};
 
 use crate::{APIConfig, JSApiSet};
 
-pub(super) use config::HTTPConfig;
+pub use config::HTTPConfig;
+pub use error::HttpError;
+mod client;
 pub mod config;
-
+mod error;
+mod fixtures;
+mod headers;
+mod host;
+mod rate_limit;
+#[cfg(all(feature = "http-wasi", target_arch = "wasm32"))]
+mod wasi_http;
 
 pub(super) struct HTTP;
 
 impl JSApiSet for HTTP {
-    
+
     fn register(&self, runtime: &Runtime, config: &APIConfig) -> Result<()> {
         let context = runtime.context();
         let global = context.global_object()?;
@@ -21,31 +30,95 @@ impl JSApiSet for HTTP {
             javy_object = context.object_value()?;
             global.set_property("Node", javy_object)?;
         }
-        
+
         let configcp = config.http.clone();
 
         global.set_property(
             "__request",
             context.wrap_callback(move |_, _this_arg, args| {
-                let [endpoint, data, headers, method, ..] = args else {
+                let [endpoint, data, headers, method, redirect, ..] = args else {
                     anyhow::bail!("Invalid number of parameters");
                 };
 
-                // Get the domain and endpoint
-                let endpoint = endpoint.to_string();
-                let method = method.to_string();
-
-                // Check of the url and method is allowed
-                if configcp.can_access(&method, &endpoint) {
-                    
-                    panic!("Not implemented")
+                let endpoint: String = endpoint.try_into()?;
+                let method: String = method.try_into()?;
+                let method = method.to_uppercase();
+                let redirect = if redirect.is_null_or_undefined() {
+                    client::RedirectMode::Follow
+                } else {
+                    client::RedirectMode::try_from(redirect.as_str()?)?
+                };
 
+                let headers: HashMap<String, JSValue> = headers.try_into()?;
+                let headers = headers
+                    .into_iter()
+                    .map(|(name, value)| Ok((name, value.try_into()?)))
+                    .collect::<Result<Vec<(String, String)>>>()?;
+                let body = if data.is_null_or_undefined() {
+                    Vec::new()
+                } else if data.is_str() {
+                    data.as_str()?.as_bytes().to_vec()
                 } else {
-                    anyhow::bail!("Access denied");
+                    data.as_bytes()?.to_vec()
+                };
+
+                // Checks the access rules and limits before anything is sent
+                let completed = client::send(
+                    &configcp,
+                    host::Request {
+                        method,
+                        url: endpoint,
+                        headers,
+                        body,
+                        ..Default::default()
+                    },
+                    redirect,
+                )?;
+                let response = completed.response;
+
+                // Like Node, names are lowercased and repeated headers joined
+                let mut headers: HashMap<String, JSValue> = HashMap::new();
+                for (name, value) in response.headers {
+                    let name = name.to_ascii_lowercase();
+                    let value = match headers.remove(&name) {
+                        Some(JSValue::String(previous)) => format!("{}, {}", previous, value),
+                        _ => value,
+                    };
+                    headers.insert(name, value.into());
                 }
-                Ok(0.into())
+
+                let mut result = HashMap::new();
+                result.insert("status", JSValue::Int(response.status as i32));
+                result.insert("headers", JSValue::Object(headers));
+                result.insert("body", JSValue::ArrayBuffer(response.body));
+                result.insert("url", JSValue::String(completed.url));
+                result.insert("redirected", JSValue::Bool(completed.redirected));
+                Ok(JSValue::from_hashmap(result))
+            })?,
+        )?;
+
+        // Body conversions of fetch, `TextDecoder` may not be registered
+        global.set_property(
+            "__httpDecodeUtf8",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [data, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+                Ok(String::from_utf8_lossy(data.as_bytes()?).into_owned().into())
+            })?,
+        )?;
+
+        global.set_property(
+            "__httpEncodeUtf8",
+            context.wrap_callback(move |_, _this_arg, args| {
+                let [data, ..] = args else {
+                    anyhow::bail!("Invalid number of parameters");
+                };
+                let data: String = data.try_into()?;
+                Ok(data.into_bytes().into())
             })?,
         )?;
+
         context.eval_global("http.js", include_str!("http.js"))?;
         Ok(())
     }
@@ -58,7 +131,385 @@ impl JSApiSet for HTTP {
 #[cfg(test)]
 mod tests {
     use crate::{http::HTTP, APIConfig, JSApiSet};
+    use super::config::{
+        HeaderPolicy, HttpAccessRule, HttpLimits, RateLimit, RateLimitPolicy, RedirectPolicy, SecretSource,
+    };
+    use super::error::{self, HttpError};
+    use super::host::{stand_in, Request, Response};
     use anyhow::Result;
     use javy::Runtime;
+    use std::cell::RefCell;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    /// Answers every request with `response`, the returned list collects
+    /// the requests that reached the host.
+    fn serve(response: Response) -> Rc<RefCell<Vec<Request>>> {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let receivedcp = received.clone();
+        stand_in::serve(move |request| {
+            receivedcp.borrow_mut().push(request);
+            Ok(Response {
+                status: response.status,
+                headers: response.headers.clone(),
+                body: response.body.clone(),
+            })
+        });
+        received
+    }
+
+    /// Answers by path, see the handler for what each path does.
+    fn serve_paths() -> Rc<RefCell<Vec<Request>>> {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let receivedcp = received.clone();
+        stand_in::serve(move |request| {
+            let url = url::Url::parse(&request.url).unwrap();
+            let response = match url.path() {
+                "/start" => redirect(302, "/next"),
+                "/next" => Response { status: 200, body: b"done".to_vec(), ..Default::default() },
+                "/away" => redirect(301, "http://other.test/x"),
+                "/loop" => redirect(307, "/loop"),
+                "/slow" if request.timeout_ms.is_some_and(|ms| ms < 1000) => {
+                    return Err(HttpError::new(error::TIMEOUT, "timed out"));
+                }
+                // Ignores `max_response_bytes`, the guest checks it again
+                "/big" => Response { status: 200, body: vec![b'x'; 100], ..Default::default() },
+                _ => Response { status: 404, ..Default::default() },
+            };
+            receivedcp.borrow_mut().push(request);
+            Ok(response)
+        });
+        received
+    }
+
+    fn redirect(status: u16, location: &str) -> Response {
+        Response {
+            status,
+            headers: vec![header("Location", location)],
+            body: Vec::new(),
+        }
+    }
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_http_request() -> Result<()> {
+        let received = serve(Response {
+            status: 201,
+            headers: vec![header("X-Test", "a"), header("X-Test", "b")],
+            body: b"hello".to_vec(),
+        });
+
+        let runtime = Runtime::default();
+        let mut config = APIConfig::default();
+        config.http.allow_access("POST", r"^127\.0\.0\.1$", "^/items$");
+
+        HTTP.register(&runtime, &config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            let denied = 'none';
+            try { Node.HTTP.request('http://127.0.0.1:8080/other', 'ping', {}, 'POST') } catch (e) { denied = e.message }
+            const res = Node.HTTP.request('http://127.0.0.1:8080/items?id=1', 'ping', { 'Content-Type': 'text/plain' }, 'post');
+            result = [
+                res.status,
+                res.headers['x-test'],
+                String.fromCharCode(...new Uint8Array(res.body)),
+                denied,
+            ].join(',');
+        "#)?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "201,a, b,hello,Access denied: POST http://127.0.0.1:8080/other");
+
+        let received = received.borrow();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].url, "http://127.0.0.1:8080/items?id=1");
+        assert_eq!(received[0].headers, vec![header("Content-Type", "text/plain")]);
+        assert_eq!(received[0].body, b"ping");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch() -> Result<()> {
+        let received = serve(Response {
+            status: 404,
+            headers: vec![header("Content-Type", "application/json")],
+            body: "{\"error\":\"é not found\"}".as_bytes().to_vec(),
+        });
+
+        let runtime = Runtime::default();
+        let mut config = APIConfig::default();
+        config.http.allow_access("PUT", r"^127\.0\.0\.1$", "^/items/1$");
+
+        HTTP.register(&runtime, &config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            result = [];
+            fetch('http://127.0.0.1/items/1', { method: 'PUT', body: new Uint8Array([104, 105]), headers: { 'content-type': 'text/x-test' } })
+                .then((res) => {
+                    result.push(res.status, res.ok, res.headers.get('Content-Type'), res.url);
+                    return res.json();
+                })
+                .then((json) => result.push(json.error))
+                .then(() => fetch('http://127.0.0.1/items/2', { method: 'PUT' }))
+                .catch((e) => result.push(e instanceof TypeError, e.code))
+                .then(() => fetch('http://127.0.0.1/items/1', { method: 'PUT', signal: { aborted: true } }))
+                .catch((e) => result.push(e.name));
+        "#)?;
+        ctx.execute_pending()?;
+        let result: String = ctx.eval_global("result.js", "result.join(',')")?.try_into()?;
+        assert_eq!(result, "404,false,application/json,http://127.0.0.1/items/1,é not found,true,EACCES,AbortError");
+
+        let received = received.borrow();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].headers, vec![header("content-type", "text/x-test")]);
+        assert_eq!(received[0].body, b"hi");
+        Ok(())
+    }
+
+    #[test]
+    fn test_limits() -> Result<()> {
+        let received = serve_paths();
+
+        let runtime = Runtime::default();
+        let mut config = APIConfig::default();
+        config.http.allow_access("GET|POST", r"127\.0\.0\.1", ".*");
+        config.http.set_limits(HttpLimits {
+            timeout_ms: Some(500),
+            max_request_bytes: Some(4),
+            max_response_bytes: Some(50),
+            redirect: RedirectPolicy::Follow(3),
+            ..Default::default()
+        });
+
+        HTTP.register(&runtime, &config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            const checks = [];
+            function attempt(f) { try { f() } catch (e) { checks.push(e.code) } }
+            const res = Node.HTTP.request('http://127.0.0.1/start', null, {}, 'POST');
+            checks.push(res.status, res.url, res.redirected);
+            attempt(() => Node.HTTP.request('http://127.0.0.1/away'));
+            attempt(() => Node.HTTP.request('http://127.0.0.1/loop'));
+            attempt(() => Node.HTTP.request('http://127.0.0.1/slow'));
+            attempt(() => Node.HTTP.request('http://127.0.0.1/big'));
+            attempt(() => Node.HTTP.request('http://127.0.0.1/next', 'hello', {}, 'POST'));
+            result = '';
+            fetch('http://127.0.0.1/start', { redirect: 'manual' })
+                .then((res) => checks.push(res.status, res.redirected))
+                .then(() => fetch('http://127.0.0.1/start', { redirect: 'error' }))
+                .catch((e) => checks.push(e.name, e.code))
+                .then(() => { result = checks.join(',') });
+        "#)?;
+        ctx.execute_pending()?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(
+            result,
+            "200,http://127.0.0.1/next,true,EACCES,ERR_HTTP_TOO_MANY_REDIRECTS,ETIMEDOUT,\
+             ERR_HTTP_RESPONSE_TOO_LARGE,ERR_HTTP_REQUEST_TOO_LARGE,302,false,HttpError,ERR_HTTP_REDIRECT_DENIED"
+        );
+
+        let received = received.borrow();
+        // The POST redirected by a 302 goes on as a GET
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[1].method, "GET");
+        assert_eq!(received[1].url, "http://127.0.0.1/next");
+        assert!(received[0].timeout_ms.is_some_and(|ms| ms <= 500));
+        assert_eq!(received[0].max_response_bytes, Some(50));
+        // The first request and three redirects
+        assert_eq!(received.iter().filter(|r| r.url.ends_with("/loop")).count(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_redirect_policies() -> Result<()> {
+        serve_paths();
+
+        let runtime = Runtime::default();
+        let mut config = APIConfig::default();
+        config.http.allow_access("GET", r"127\.0\.0\.1|other\.test", ".*");
+        config.http.set_limits(HttpLimits {
+            redirect: RedirectPolicy::SameOrigin,
+            ..Default::default()
+        });
+        HTTP.register(&runtime, &config)?;
+        let result: String = runtime.context().eval_global("test.js", r#"
+            const checks = [Node.HTTP.request('http://127.0.0.1/start').status];
+            try { Node.HTTP.request('http://127.0.0.1/away') } catch (e) { checks.push(e.code) }
+            checks.join(',');
+        "#)?.try_into()?;
+        assert_eq!(result, "200,ERR_HTTP_REDIRECT_DENIED");
+
+        let runtime = Runtime::default();
+        config.http.set_limits(HttpLimits {
+            redirect: RedirectPolicy::None,
+            ..Default::default()
+        });
+        HTTP.register(&runtime, &config)?;
+        let status: i32 = runtime.context()
+            .eval_global("test.js", "Node.HTTP.request('http://127.0.0.1/start').status")?
+            .try_into()?;
+        assert_eq!(status, 302);
+        assert!(RedirectPolicy::try_from("sideways").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limits() -> Result<()> {
+        let received = serve(Response {
+            status: 200,
+            ..Default::default()
+        });
+
+        let runtime = Runtime::default();
+        let mut config = APIConfig::default();
+        config.http.add_rule(
+            HttpAccessRule::new("GET", r"api\.test", ".*")
+                .unwrap()
+                .with_rate_limit(RateLimit::new(0.001, 2).unwrap()),
+        );
+        config.http.allow_access("GET", r"other\.test", ".*");
+        config.http.set_limits(HttpLimits {
+            rate_limited: RateLimitPolicy::Reject,
+            ..Default::default()
+        });
 
-}
\ No newline at end of file
+        HTTP.register(&runtime, &config)?;
+        let result: String = runtime.context().eval_global("test.js", r#"
+            const checks = [];
+            for (const url of ['http://api.test/', 'http://api.test/a', 'http://api.test/b', 'http://other.test/']) {
+                try { checks.push(Node.HTTP.request(url).status) } catch (e) { checks.push(e.code) }
+            }
+            checks.join(',');
+        "#)?.try_into()?;
+        assert_eq!(result, "200,200,ERR_HTTP_RATE_LIMITED,200");
+        assert_eq!(received.borrow().len(), 3);
+        assert!(RateLimitPolicy::try_from("drop").is_err());
+        Ok(())
+    }
+
+    /// Stands in for the environment and files of the module.
+    #[derive(Debug)]
+    struct TestSecrets;
+
+    impl SecretSource for TestSecrets {
+        fn env(&self, name: &str) -> Option<String> {
+            (name == "JAVY_TEST_HTTP_TOKEN").then(|| "s3cret".to_string())
+        }
+
+        fn file(&self, path: &Path) -> Option<String> {
+            (path == Path::new("./test_http_tenant.txt")).then(|| "tenant-1\n".to_string())
+        }
+    }
+
+    #[test]
+    fn test_header_policies() -> Result<()> {
+        let received = serve(Response {
+            status: 200,
+            ..Default::default()
+        });
+        let secrets: Rc<dyn SecretSource> = Rc::new(TestSecrets);
+
+        let runtime = Runtime::default();
+        let mut config = APIConfig::default();
+        config.http.allow_access("GET", r"api\.test|other\.test", ".*");
+        config.http.add_header_policy(
+            HeaderPolicy::new(r"api\.test", "/v1/.*")
+                .unwrap()
+                .with_injected("Authorization", "Bearer ${env:JAVY_TEST_HTTP_TOKEN}")
+                .unwrap()
+                .with_injected("X-Tenant", "${file:./test_http_tenant.txt}")
+                .unwrap()
+                .with_stripped("X-Debug")
+                .with_protected("X-Tenant")
+                .with_source(secrets.clone()),
+        );
+        config.http.add_header_policy(
+            HeaderPolicy::new(r"other\.test", ".*")
+                .unwrap()
+                .with_injected("X-Missing", "${env:JAVY_TEST_HTTP_UNSET}")
+                .unwrap()
+                .with_source(secrets),
+        );
+        assert!(HeaderPolicy::new(".*", ".*").unwrap().with_injected("X", "${vault:key}").is_none());
+
+        HTTP.register(&runtime, &config)?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            const request = new Request('https://api.test/v1/items', { headers: { authorization: 'mine', 'x-debug': '1', accept: 'text/plain' } });
+            result = [];
+            fetch(request)
+                .then(() => result.push(request.headers.get('authorization'), request.headers.has('x-tenant')))
+                .then(() => fetch('https://api.test/v1/items', { headers: { 'X-Tenant': 'other' } }))
+                .catch((e) => result.push(e.code))
+                .then(() => fetch('https://api.test/v2/items'))
+                .then(() => fetch('https://other.test/'))
+                .catch((e) => result.push(e.code))
+                .then(() => { result = result.join(',') });
+        "#)?;
+        ctx.execute_pending()?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(result, "mine,false,ERR_HTTP_HEADER_FORBIDDEN,ERR_HTTP_HEADER_UNAVAILABLE");
+
+        let received = received.borrow();
+        assert_eq!(received.len(), 2);
+        let mut headers = received[0].headers.clone();
+        headers.sort();
+        assert_eq!(
+            headers,
+            vec![
+                header("accept", "text/plain"),
+                header("authorization", "Bearer s3cret"),
+                header("x-tenant", "tenant-1"),
+            ]
+        );
+        // Only the URLs matched by the policy get the values
+        assert!(received[1].headers.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_response_headers() -> Result<()> {
+        let runtime = Runtime::default();
+        HTTP.register(&runtime, &APIConfig::default())?;
+        let ctx = runtime.context();
+        ctx.eval_global("test.js", r#"
+            const headers = new Headers([['B', '2'], ['a', ' 1 '], ['b', '3']]);
+            const checks = [
+                [...headers].join('|'),
+                headers.get('b'),
+                headers.has('A'),
+            ];
+            try { new Headers({ 'bad name': 'x' }) } catch (e) { checks.push(e.constructor.name) }
+            try { new Response('x', { status: 99 }) } catch (e) { checks.push(e.constructor.name) }
+            try { new Request('http://example.com', { body: 'x' }) } catch (e) { checks.push(e.constructor.name) }
+
+            const request = new Request('http://example.com', { method: 'post', body: 'data' });
+            checks.push(request.method, request.headers.get('content-type'));
+            const cloned = request.clone();
+            const copy = new Request(request);
+            checks.push(request.bodyUsed, cloned.bodyUsed, copy.bodyUsed);
+            try { new Request(request) } catch (e) { checks.push(e.constructor.name) }
+
+            const response = Response.json({ a: 1 }, { status: 201 });
+            checks.push(response.status, response.ok, response.headers.get('content-type'));
+            result = [];
+            response.clone().text()
+                .then((text) => checks.push(text))
+                .then(() => response.arrayBuffer())
+                .then((buffer) => checks.push(buffer.byteLength, response.bodyUsed))
+                .then(() => response.text())
+                .catch((e) => checks.push(e.constructor.name))
+                .then(() => { result = checks.join(',') });
+        "#)?;
+        ctx.execute_pending()?;
+        let result: String = ctx.global_object()?.get_property("result")?.try_into()?;
+        assert_eq!(
+            result,
+            "a,1|b,2, 3,2, 3,true,TypeError,RangeError,TypeError,POST,text/plain;charset=UTF-8,true,false,false,TypeError,201,true,application/json,{\"a\":1},7,true,TypeError"
+        );
+        Ok(())
+    }
+}
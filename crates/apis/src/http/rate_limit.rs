@@ -0,0 +1,211 @@
+//! Rate limits and caps on requests in flight of the access rules.
+//!
+//! Each rule keeps a token bucket holding up to `burst` requests, refilled
+//! at `requests_per_second`. The state is shared by the clones of a rule, so
+//! every copy of an [`super::HTTPConfig`] counts against the same limits.
+//!
+//! A rule with `max_concurrent` also counts the requests it allowed until
+//! they complete, and rejects new ones with `ERR_HTTP_RATE_LIMITED` past the
+//! cap. They are never queued: nothing completes while the module waits.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::config::RateLimitPolicy;
+use super::error::{self, HttpError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Tokens added to the bucket every second.
+    pub requests_per_second: f64,
+    /// Requests that can be made at once after a quiet period, the size of
+    /// the bucket.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Returns `None` unless `requests_per_second` is a positive number and
+    /// `burst` is at least 1.
+    pub fn new(requests_per_second: f64, burst: u32) -> Option<Self> {
+        if !requests_per_second.is_finite() || requests_per_second <= 0.0 || burst == 0 {
+            return None;
+        }
+        Some(RateLimit {
+            requests_per_second,
+            burst,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    tokens: f64,
+    /// `None` until the first request, the bucket starts full.
+    updated: Option<Instant>,
+    in_flight: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Limiter {
+    pub(super) rate: Option<RateLimit>,
+    pub(super) max_concurrent: Option<u32>,
+    state: Rc<RefCell<State>>,
+}
+
+impl Limiter {
+    pub(super) fn is_active(&self) -> bool {
+        self.rate.is_some() || self.max_concurrent.is_some()
+    }
+
+    fn refill(&self, state: &mut State, now: Instant) {
+        let Some(rate) = self.rate else {
+            return;
+        };
+        let burst = rate.burst as f64;
+        state.tokens = match state.updated {
+            Some(updated) => {
+                let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+                (state.tokens + elapsed * rate.requests_per_second).min(burst)
+            }
+            None => burst,
+        };
+        state.updated = Some(now);
+    }
+
+    /// How long until the bucket has a token.
+    fn wait(&self, now: Instant) -> Duration {
+        let mut state = self.state.borrow_mut();
+        self.refill(&mut state, now);
+        match self.rate {
+            Some(rate) if state.tokens < 1.0 => {
+                Duration::from_secs_f64((1.0 - state.tokens) / rate.requests_per_second)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn take(&self, now: Instant) {
+        let mut state = self.state.borrow_mut();
+        self.refill(&mut state, now);
+        if self.rate.is_some() {
+            state.tokens -= 1.0;
+        }
+        state.in_flight += 1;
+    }
+}
+
+/// A request counted as in flight by its limiters until it is dropped.
+pub(super) struct Permit {
+    limiters: Vec<Limiter>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        for limiter in &self.limiters {
+            let mut state = limiter.state.borrow_mut();
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// Takes a token from every limiter of the rules allowing the request,
+/// waiting for them with [`RateLimitPolicy::Wait`] as long as `remaining`,
+/// the time left before the request times out, allows. The request counts
+/// against `max_concurrent` until the returned permit is dropped.
+pub(super) fn acquire(
+    limiters: Vec<Limiter>,
+    policy: RateLimitPolicy,
+    remaining: Option<Duration>,
+    method: &str,
+    url: &str,
+) -> Result<Permit, HttpError> {
+    for limiter in &limiters {
+        if let Some(max) = limiter.max_concurrent {
+            // Nothing else runs while the module waits, so a slot can't be
+            // freed by waiting
+            if limiter.state.borrow().in_flight >= max {
+                return Err(HttpError::new(
+                    error::RATE_LIMITED,
+                    format!("{} requests already in flight for {} {}", max, method, url),
+                ));
+            }
+        }
+    }
+
+    let wait = limiters
+        .iter()
+        .map(|limiter| limiter.wait(Instant::now()))
+        .max()
+        .unwrap_or_default();
+    if !wait.is_zero() {
+        if policy == RateLimitPolicy::Reject || remaining.is_some_and(|remaining| wait >= remaining) {
+            return Err(HttpError::new(
+                error::RATE_LIMITED,
+                format!(
+                    "Rate limit reached for {} {}, retry in {} ms",
+                    method,
+                    url,
+                    wait.as_millis().max(1)
+                ),
+            ));
+        }
+        std::thread::sleep(wait);
+    }
+
+    let now = Instant::now();
+    for limiter in &limiters {
+        limiter.take(now);
+    }
+    Ok(Permit { limiters })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{acquire, Limiter, RateLimit};
+    use crate::http::config::RateLimitPolicy;
+    use crate::http::error;
+
+    fn limiter(rate: Option<RateLimit>, max_concurrent: Option<u32>) -> Limiter {
+        Limiter {
+            rate,
+            max_concurrent,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let reject = limiter(RateLimit::new(1.0, 2), None);
+        let take = || acquire(vec![reject.clone()], RateLimitPolicy::Reject, None, "GET", "http://a.test/");
+        assert!(take().is_ok());
+        assert!(take().is_ok());
+        assert_eq!(take().err().unwrap().code(), error::RATE_LIMITED);
+
+        let wait = limiter(RateLimit::new(20.0, 1), None);
+        let take = |remaining| acquire(vec![wait.clone()], RateLimitPolicy::Wait, remaining, "GET", "http://b.test/");
+        assert!(take(None).is_ok());
+        let started = std::time::Instant::now();
+        assert!(take(None).is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        // Waiting would take longer than the request may
+        assert_eq!(
+            take(Some(Duration::from_millis(1))).err().unwrap().code(),
+            error::RATE_LIMITED
+        );
+
+        assert!(RateLimit::new(0.0, 1).is_none());
+        assert!(RateLimit::new(1.0, 0).is_none());
+    }
+
+    #[test]
+    fn test_max_concurrent() {
+        let capped = limiter(None, Some(1));
+        let take = || acquire(vec![capped.clone()], RateLimitPolicy::Wait, None, "GET", "http://c.test/");
+        let permit = take().unwrap();
+        assert_eq!(take().err().unwrap().code(), error::RATE_LIMITED);
+        drop(permit);
+        assert!(take().is_ok());
+    }
+}
@@ -0,0 +1,30 @@
+// Local server for the wasi:http tests, see `make test-apis-wasi-http`.
+// Answers `/large` with 1000 bytes, `/count` with the number of other
+// requests it got and anything else with `METHOD PATH X-TEST BODY`.
+const http = require("http");
+
+const port = Number(process.argv[2] || 8787);
+let served = 0;
+
+http
+    .createServer((req, res) => {
+        const chunks = [];
+        req.on("data", (chunk) => chunks.push(chunk));
+        req.on("end", () => {
+            if (req.url === "/count") {
+                res.writeHead(200, { "content-type": "text/plain" });
+                res.end(String(served));
+                return;
+            }
+            served += 1;
+            if (req.url === "/large") {
+                res.writeHead(200, { "content-type": "text/plain" });
+                res.end("x".repeat(1000));
+                return;
+            }
+            const body = Buffer.concat(chunks).toString();
+            res.writeHead(200, { "content-type": "text/plain", "x-echo": "yes" });
+            res.end(`${req.method} ${req.url} ${req.headers["x-test"]} ${body}`);
+        });
+    })
+    .listen(port, "127.0.0.1");
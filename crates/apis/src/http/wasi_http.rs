@@ -0,0 +1,222 @@
+//! `wasi:http/outgoing-handler` backend, built with the `http-wasi` feature
+//! for hosts that implement WASI preview2 HTTP instead of `javy_http_v1`.
+//!
+//! It takes the place of the host import and nothing else: the access rules,
+//! limits and header policies of [`super::HTTPConfig`] have already been
+//! applied when [`send`] is called, and the outgoing handler doesn't follow
+//! redirects either.
+// The tests use the stand-in host, only the wasip2 ones call this directly
+#![cfg_attr(test, allow(dead_code))]
+
+use url::{Position, Url};
+use wasi::http::outgoing_handler;
+use wasi::http::types::{
+    ErrorCode, Fields, IncomingBody, Method, OutgoingBody, OutgoingRequest, RequestOptions, Scheme,
+};
+use wasi::io::streams::StreamError;
+
+use super::error::{self, HttpError};
+use super::host::{Request, Response};
+
+/// Bytes written to or read from a stream at a time.
+const CHUNK_SIZE: usize = 4096;
+
+fn failed(message: impl Into<String>) -> HttpError {
+    HttpError::new(error::REQUEST_FAILED, message)
+}
+
+fn method(method: &str) -> Method {
+    match method {
+        "GET" => Method::Get,
+        "HEAD" => Method::Head,
+        "POST" => Method::Post,
+        "PUT" => Method::Put,
+        "DELETE" => Method::Delete,
+        "CONNECT" => Method::Connect,
+        "OPTIONS" => Method::Options,
+        "TRACE" => Method::Trace,
+        "PATCH" => Method::Patch,
+        other => Method::Other(other.to_string()),
+    }
+}
+
+fn from_error_code(code: ErrorCode) -> HttpError {
+    match code {
+        ErrorCode::ConnectionTimeout
+        | ErrorCode::ConnectionReadTimeout
+        | ErrorCode::ConnectionWriteTimeout
+        | ErrorCode::HttpResponseTimeout => HttpError::new(error::TIMEOUT, format!("{:?}", code)),
+        ErrorCode::HttpResponseBodySize(_) => HttpError::new(error::RESPONSE_TOO_LARGE, format!("{:?}", code)),
+        code => failed(format!("{:?}", code)),
+    }
+}
+
+/// Sends `request` through the outgoing handler and reads the whole
+/// response, giving up on bodies over `max_response_bytes`.
+pub(super) fn send(request: &Request) -> Result<Response, HttpError> {
+    let url = Url::parse(&request.url).map_err(|e| failed(format!("Invalid URL: {}", e)))?;
+
+    let headers: Vec<(String, Vec<u8>)> = request
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.as_bytes().to_vec()))
+        .collect();
+    let fields = Fields::from_list(&headers).map_err(|e| failed(format!("Invalid headers: {:?}", e)))?;
+
+    let outgoing = OutgoingRequest::new(fields);
+    let scheme = match url.scheme() {
+        "http" => Scheme::Http,
+        "https" => Scheme::Https,
+        other => Scheme::Other(other.to_string()),
+    };
+    outgoing
+        .set_method(&method(&request.method))
+        .and_then(|_| outgoing.set_scheme(Some(&scheme)))
+        .and_then(|_| outgoing.set_authority(Some(&url[Position::BeforeHost..Position::AfterPort])))
+        .and_then(|_| outgoing.set_path_with_query(Some(&url[Position::BeforePath..Position::AfterQuery])))
+        .map_err(|_| failed(format!("Invalid request: {} {}", request.method, request.url)))?;
+    let body = outgoing.body().map_err(|_| failed("Request body already taken"))?;
+
+    let options = RequestOptions::new();
+    if let Some(timeout_ms) = request.timeout_ms {
+        let nanos = Some(timeout_ms.saturating_mul(1_000_000));
+        // Hosts may not support every timeout, the guest still checks the
+        // total time
+        let _ = options.set_connect_timeout(nanos);
+        let _ = options.set_first_byte_timeout(nanos);
+        let _ = options.set_between_bytes_timeout(nanos);
+    }
+
+    let future = outgoing_handler::handle(outgoing, Some(options)).map_err(from_error_code)?;
+    {
+        let stream = body.write().map_err(|_| failed("Request body stream already taken"))?;
+        for chunk in request.body.chunks(CHUNK_SIZE) {
+            stream
+                .blocking_write_and_flush(chunk)
+                .map_err(|e| failed(format!("Could not write the request body: {:?}", e)))?;
+        }
+    }
+    OutgoingBody::finish(body, None).map_err(from_error_code)?;
+
+    let incoming = loop {
+        match future.get() {
+            Some(result) => break result,
+            None => future.subscribe().block(),
+        }
+    };
+    let incoming = incoming
+        .map_err(|_| failed("Response already taken"))?
+        .map_err(from_error_code)?;
+
+    let status = incoming.status();
+    let headers = incoming
+        .headers()
+        .entries()
+        .into_iter()
+        .map(|(name, value)| (name, String::from_utf8_lossy(&value).into_owned()))
+        .collect();
+
+    let incoming_body = incoming.consume().map_err(|_| failed("Response body already taken"))?;
+    let mut body = Vec::new();
+    {
+        let stream = incoming_body.stream().map_err(|_| failed("Response body stream already taken"))?;
+        loop {
+            match stream.blocking_read(CHUNK_SIZE as u64) {
+                Ok(chunk) => body.extend_from_slice(&chunk),
+                Err(StreamError::Closed) => break,
+                Err(e) => return Err(failed(format!("Could not read the response body: {:?}", e))),
+            }
+            if let Some(max) = request.max_response_bytes {
+                if body.len() as u64 > max {
+                    return Err(HttpError::new(
+                        error::RESPONSE_TOO_LARGE,
+                        format!("Response body is over the limit of {} bytes", max),
+                    ));
+                }
+            }
+        }
+    }
+    drop(IncomingBody::finish(incoming_body));
+
+    Ok(Response { status, headers, body })
+}
+
+// Needs wasmtime's wasi-http and a server answering with the request it
+// got, see `make test-apis-wasi-http`
+#[cfg(all(test, target_env = "p2"))]
+mod tests {
+    use super::send;
+    use crate::http::client::{self, RedirectMode};
+    use crate::http::error::{self, HttpError};
+    use crate::http::host::{stand_in, Request};
+    use crate::http::HTTPConfig;
+
+    fn test_server() -> String {
+        std::env::var("JAVY_HTTP_TEST_SERVER").expect("JAVY_HTTP_TEST_SERVER is not set, see `make test-apis-wasi-http`")
+    }
+
+    /// Requests the server got, not counting the ones to `/count`.
+    fn requests_served(server: &str) -> usize {
+        let response = send(&Request {
+            method: "GET".to_string(),
+            url: format!("{}/count", server),
+            ..Default::default()
+        })
+        .unwrap();
+        String::from_utf8(response.body).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs the local server of `make test-apis-wasi-http`"]
+    fn test_wasi_http() {
+        let server = test_server();
+
+        let response = send(&Request {
+            method: "POST".to_string(),
+            url: format!("{}/echo?id=1", server),
+            headers: vec![("X-Test".to_string(), "a".to_string())],
+            body: b"ping".to_vec(),
+            timeout_ms: Some(5000),
+            max_response_bytes: None,
+        })
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"POST /echo?id=1 a ping");
+        assert!(response.headers.contains(&("x-echo".to_string(), "yes".to_string())));
+
+        let error = send(&Request {
+            method: "GET".to_string(),
+            url: format!("{}/large", server),
+            max_response_bytes: Some(100),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(error.code(), error::RESPONSE_TOO_LARGE);
+    }
+
+    #[test]
+    #[ignore = "needs the local server of `make test-apis-wasi-http`"]
+    fn test_wasi_http_access_rules() {
+        let server = test_server();
+        // The tests answer with the stand-in host, route it to wasi:http
+        stand_in::serve(|request| send(&request));
+        let mut config = HTTPConfig::new();
+        config.allow_access("GET", r"127\.0\.0\.1", "/allowed");
+
+        let before = requests_served(&server);
+        let request = |method: &str, path: &str| Request {
+            method: method.to_string(),
+            url: format!("{}{}", server, path),
+            ..Default::default()
+        };
+        for denied in [request("GET", "/denied"), request("POST", "/allowed")] {
+            let error = client::send(&config, denied, RedirectMode::Follow).err().unwrap();
+            assert_eq!(error.downcast_ref::<HttpError>().unwrap().code(), error::ACCESS_DENIED);
+        }
+        assert_eq!(requests_served(&server), before);
+
+        let completed = client::send(&config, request("GET", "/allowed"), RedirectMode::Follow).unwrap();
+        assert_eq!(completed.response.status, 200);
+        assert_eq!(requests_served(&server), before + 1);
+    }
+}
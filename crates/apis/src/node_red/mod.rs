@@ -7,67 +7,80 @@ use crate::{APIConfig, JSApiSet};
 
 pub(super) struct NodeRed;
 
-extern "Rust" {
+/// Version of the Node-RED host functions, bumped on any change to their
+/// names, signatures or encoding. It is part of the import module name and
+/// exported as `node_red_abi_version`, see the `javy-node-red-host` crate.
+pub const ABI_VERSION: i32 = 1;
+
+/// Lets hosts check the module was built for their version of the imports.
+#[export_name = "node_red_abi_version"]
+pub extern "C" fn abi_version() -> i32 {
+    ABI_VERSION
+}
+
+// Values are JSON: the `*_size` functions return the length of a value, the
+// reading functions write it at `data + offset`, up to `length` bytes, and
+// return the bytes written, the other ones hand over `length` bytes at
+// `data + offset`.
+#[link(wasm_import_module = "node_red_v1")]
+extern "C" {
 
     /// Returns the size of the node red message encoded as JSON
-    #[allow(dead_code)]
-    pub fn node_red_msg_size() -> usize;
+    #[link_name = "msg_size"]
+    fn node_red_msg_size() -> i32;
 
     /// Ask the host to write the message JSON in the buff
-    #[allow(dead_code)]
-    pub fn node_red_msg(data: *const u8, offset: i32, length: i32) -> usize; // Implemented by the host
+    #[link_name = "msg"]
+    fn node_red_msg(data: *const u8, offset: i32, length: i32) -> i32;
 
     /// Send a custom message using the node red host
-    #[allow(dead_code)]
-    pub fn node_red_send(data: *const u8, offset: i32, length: i32);
+    #[link_name = "send"]
+    fn node_red_send(data: *const u8, offset: i32, length: i32);
 
-    /// Send a custom message using the node red host
-    #[allow(dead_code)]
-    pub fn node_red_warn(data: *const u8, offset: i32, length: i32);
+    /// Warn with a custom message using the node red host
+    #[link_name = "warn"]
+    fn node_red_warn(data: *const u8, offset: i32, length: i32);
 
     /// Error a custom message using the node red host
-    #[allow(dead_code)]
-    pub fn node_red_error(data: *const u8, offset: i32, length: i32);
+    #[link_name = "error"]
+    fn node_red_error(data: *const u8, offset: i32, length: i32);
 
     /// Call the done function of the node red host
-    #[allow(dead_code)]
-    pub fn node_red_done(data: *const u8, offset: i32, length: i32);
-
+    #[link_name = "done"]
+    fn node_red_done(data: *const u8, offset: i32, length: i32);
 
     /// Returns the size of the node red node struct encoded as JSON
-    #[allow(dead_code)]
-    pub fn node_red_node_length() -> usize;
+    #[link_name = "node_size"]
+    fn node_red_node_length() -> i32;
 
     /// Ask the host to write the node JSON in the buff
-    #[allow(dead_code)]
-    pub fn node_red_node(data: *const u8, offset: i32, length: i32) -> usize; // Implemented by the host
-    
-    #[allow(dead_code)]
-    pub fn node_red_result(data: *const u8, offset: i32, length:i32);
-
-    #[allow(dead_code)]
-    pub fn node_red_register(data: *const u8, offset: i32, length:i32);
-    
-    #[allow(dead_code)]
-    pub fn node_emit(data: *const u8, offset: i32, length: i32);
+    #[link_name = "node"]
+    fn node_red_node(data: *const u8, offset: i32, length: i32) -> i32;
 
-    /// Returns the size of the node red message encoded as JSON
-    #[allow(dead_code)]
-    pub fn node_red_pop_size() -> usize;
+    #[link_name = "result"]
+    fn node_red_result(data: *const u8, offset: i32, length: i32);
 
-    /// Ask the host to write the message JSON in the buff
-    #[allow(dead_code)]
-    pub fn node_red_pop(data: *const u8, offset: i32, length: i32) -> usize; // Implemented by the host
+    #[link_name = "register"]
+    fn node_red_register(data: *const u8, offset: i32, length: i32);
+
+    #[link_name = "emit"]
+    fn node_emit(data: *const u8, offset: i32, length: i32);
 
+    /// Returns the size of the next node red message encoded as JSON
+    #[link_name = "pop_size"]
+    fn node_red_pop_size() -> i32;
+
+    /// Ask the host to write the next message JSON in the buff and drop it
+    #[link_name = "pop"]
+    fn node_red_pop(data: *const u8, offset: i32, length: i32) -> i32;
 
     /// Returns the size of the node context encoded as JSON
-    #[allow(dead_code)]
-    pub fn node_red_context_size() -> usize;
+    #[link_name = "context_size"]
+    fn node_red_context_size() -> i32;
 
     /// Ask the host to write the context in the buff
-    #[allow(dead_code)]
-    pub fn node_red_context(data: *const u8, offset: i32, length: i32) -> usize; // Implemented by the host
-
+    #[link_name = "context"]
+    fn node_red_context(data: *const u8, offset: i32, length: i32) -> i32;
 }
 
 impl JSApiSet for NodeRed {
@@ -1,4 +1,6 @@
+use anyhow::{anyhow, Result};
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -48,6 +50,59 @@ pub struct CompileCommandOpts {
 
     #[structopt(long = "remove-function")]
     pub funcnames: Vec<String>,
+
+    #[structopt(long = "embed-dir")]
+    /// Embeds a host directory as `<host_path>:<virtual_path>`. Its files are
+    /// served read-only by `Node.FS` without preopening the directory at runtime.
+    pub embed_dirs: Vec<EmbedDir>,
+
+    #[structopt(long = "world")]
+    /// Generates the exports of a WASI world instead of running the module as a
+    /// command. Only `http-proxy` is supported, which exports
+    /// `wasi:http/incoming-handler` and calls the default export of the module
+    /// with each request.
+    pub world: Option<World>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum World {
+    /// `wasi:http/proxy`
+    HttpProxy,
+}
+
+impl FromStr for World {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "http-proxy" => Ok(Self::HttpProxy),
+            _ => Err(anyhow!("Unsupported world {s}, expected http-proxy")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EmbedDir {
+    pub host_path: PathBuf,
+    pub virtual_path: String,
+}
+
+impl FromStr for EmbedDir {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Split on the last colon so Windows host paths keep their drive
+        let (host_path, virtual_path) = s
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Expected <host_path>:<virtual_path>, got {s}"))?;
+        if host_path.is_empty() || virtual_path.is_empty() {
+            return Err(anyhow!("Expected <host_path>:<virtual_path>, got {s}"));
+        }
+        Ok(Self {
+            host_path: PathBuf::from(host_path),
+            virtual_path: virtual_path.to_string(),
+        })
+    }
 }
 
 #[derive(Debug, StructOpt)]
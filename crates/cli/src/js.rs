@@ -146,6 +146,27 @@ impl JS {
             .collect())
     }
 
+    /// Whether the module has a default export of any kind, like the handler
+    /// of the `http-proxy` world.
+    pub fn has_default_export(&self) -> Result<bool> {
+        let module = self.parse_module()?;
+        Ok(module.body.iter().any(|item| match item {
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(_))
+            | ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(_)) => true,
+            ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(e)) => {
+                e.specifiers.iter().any(|specifier| match specifier {
+                    ExportSpecifier::Named(n) => {
+                        let exported = n.exported.as_ref().unwrap_or(&n.orig);
+                        matches!(exported, ModuleExportName::Ident(i) if &*i.sym == "default")
+                            || matches!(exported, ModuleExportName::Str(s) if &*s.value == "default")
+                    }
+                    _ => false,
+                })
+            }
+            _ => false,
+        }))
+    }
+
     fn parse_module(&self) -> Result<Module> {
         let source_map: SourceMap = Default::default();
         let file = source_map.new_source_file_from(FileName::Anon, self.source_code.clone());
@@ -311,6 +332,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_default_export() -> Result<()> {
+        let has_default = |js: &str| JS::from_string(js.to_string()).has_default_export();
+        assert!(has_default("export default { fetch(request) {} }")?);
+        assert!(has_default("export default async function(request) {}")?);
+        assert!(has_default(
+            "const handler = {}; export { handler as default };"
+        )?);
+        assert!(!has_default("export function foo() {}")?);
+        Ok(())
+    }
+
     fn parse(js: &str) -> Result<Vec<String>> {
         JS::from_string(js.to_string()).exports()
     }
@@ -22,6 +22,14 @@ fn main() -> Result<()> {
         Command::EmitProvider(opts) => emit_provider(opts),
         Command::Compile(opts) => {
             let js = JS::from_file(&opts.input)?;
+            if opts.world.is_some() {
+                if opts.dynamic || opts.wit.is_some() {
+                    bail!("--world can't be used with -d or --wit");
+                }
+                if !js.has_default_export()? {
+                    bail!("The http-proxy world calls the default export of the module, which has none");
+                }
+            }
             let exports = match (&opts.wit, &opts.wit_world) {
                 (None, None) => Ok(vec![]),
                 (None, Some(_)) => Ok(vec![]),
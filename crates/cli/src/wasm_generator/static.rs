@@ -2,10 +2,10 @@ use std::{collections::HashMap, rc::Rc, sync::OnceLock};
 
 use anyhow::{anyhow, Result};
 use binaryen::{CodegenConfig, Module};
-use walrus::{DataKind, ExportItem, FunctionBuilder, FunctionId, MemoryId, ValType};
+use walrus::{DataKind, ExportItem, FunctionBuilder, FunctionId, ImportKind, MemoryId, ValType};
 use wasi_common::{pipe::ReadPipe, WasiCtx};
-use wasmtime::Linker;
-use wasmtime_wasi::WasiCtxBuilder;
+use wasmtime::{FuncType, Linker};
+use wasmtime_wasi::{ambient_authority, Dir, WasiCtxBuilder};
 use wizer::Wizer;
 use std::fs::File;
 use std::path::PathBuf;
@@ -15,9 +15,19 @@ use wasmtime_wasi::snapshots::preview_1::add_wasi_snapshot_preview1_to_linker;
 use crate::{exports::Export, js::JS};
 use std::{cell::OnceCell};
 use super::transform::{self, SourceCodeSection};
-use crate::commands::CompileCommandOpts;
+use crate::commands::{CompileCommandOpts, World};
 static mut WASI: OnceCell<WasiCtx> = OnceCell::new();
 
+/// Exported by engines built with the `http-proxy` feature of javy-core.
+const INCOMING_HANDLER_EXPORT: &str = "wasi:http/incoming-handler@0.2.0#handle";
+
+/// Host functions only called at runtime, stubbed during pre-initialization.
+const RUNTIME_IMPORTS: [&str; 2] = ["javy_http_v1", "node_red_v1"];
+
+/// The `wasi:http` interfaces imported by engines built for the http-proxy
+/// world, and the `wasi:io` streams of their bodies.
+const HTTP_PROXY_IMPORTS: [&str; 2] = ["wasi:http/", "wasi:io/"];
+
 
 pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, http_permissions: &Option<PathBuf>, opts: &CompileCommandOpts) -> Result<Vec<u8>> {
     let wasm = include_bytes!(concat!(env!("OUT_DIR"), "/engine.wasm"));
@@ -40,16 +50,31 @@ pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, h
     };
 
 
-    let wasi = WasiCtxBuilder::new()
+    // Embedded directories are preopened at their virtual path, the engine
+    // reads them into memory during pre-initialization
+    let embed_dirs = opts
+        .embed_dirs
+        .iter()
+        .map(|dir| dir.virtual_path.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut wasi = WasiCtxBuilder::new()
         .stdin(Box::new(ReadPipe::from(js.as_bytes())))   
         // To get the filer permissions     
         .envs(&[
             ("FILE_PERMISSIONS".into(), permissions),
-            ("HTTP_PERMISSIONS".into(), http_permissions)
+            ("HTTP_PERMISSIONS".into(), http_permissions),
+            ("EMBED_DIRS".into(), embed_dirs)
         ])?
         .inherit_stdout()
-        .inherit_stderr()
-        .build();
+        .inherit_stderr();
+    for dir in &opts.embed_dirs {
+        let host_dir = Dir::open_ambient_dir(&dir.host_path, ambient_authority())
+            .map_err(|e| anyhow!("Unable to open {}: {}", dir.host_path.display(), e))?;
+        wasi = wasi.preopened_dir(host_dir, &dir.virtual_path)?;
+    }
+    let wasi = wasi.build();
     // We can't move the WasiCtx into `make_linker` since WasiCtx doesn't implement the `Copy` trait.
     // So we move the WasiCtx into a mutable static OnceLock instead.
     // Setting the value in the `OnceLock` and getting the reference back from it should be safe given
@@ -59,8 +84,13 @@ pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, h
         panic!("Failed to set WASI static variable")
     }
 
+    let http_proxy = opts.world == Some(World::HttpProxy);
+    // The network and Node-RED are provided by the host at runtime only, and
+    // wasi:http is only called when handling requests
+    let stubs = runtime_only_imports(wasm, http_proxy)?;
+
     let wasm = Wizer::new()
-        .make_linker(Some(Rc::new(|engine| {
+        .make_linker(Some(Rc::new(move |engine| {
             let mut linker = Linker::new(engine);
            /*wasmtime_wasi::add_to_linker(&mut linker, |_ctx: &mut Option<WasiCtx>| {
                 unsafe { WASI.get_mut() }.unwrap()
@@ -69,6 +99,12 @@ pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, h
             add_wasi_snapshot_preview1_to_linker(&mut linker, |_ctx: &mut Option<WasiCtx>| {
                 unsafe { WASI.get_mut() }.unwrap()
             })?;
+            for (module, name, ty) in &stubs {
+                let import = format!("{}#{}", module, name);
+                linker.func_new(module, name, ty.clone(), move |_caller, _params, _results| {
+                    Err(anyhow!("{} is not available during pre-initialization", import))
+                })?;
+            }
             Ok(linker)
         })))?
         .wasm_bulk_memory(true)
@@ -98,6 +134,12 @@ pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, h
     };
 
 
+    if http_proxy && !module.exports.iter().any(|e| e.name == INCOMING_HANDLER_EXPORT) {
+        return Err(anyhow!(
+            "The engine doesn't export {INCOMING_HANDLER_EXPORT}, build javy-core with CORE_FEATURES=http-proxy"
+        ));
+    }
+
     let realloc_export = realloc.id();
     let free_export = free.id();
     let invoke_export = invoke.id();
@@ -120,6 +162,14 @@ pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, h
     module.exports.delete(realloc_export);
     module.exports.delete(free_export);
     module.exports.delete(invoke_export);
+    if http_proxy {
+        // The component runs requests through the incoming handler, not as a
+        // command
+        let start_export = module.exports.iter().find(|e| e.name == "_start").map(|e| e.id());
+        if let Some(start_export) = start_export {
+            module.exports.delete(start_export);
+        }
+    }
 
     // TODO, delete imports based on input
     // module.imports.delete(send...)
@@ -135,9 +185,13 @@ pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, h
     let mut module = Module::read(&wasm)
         .map_err(|_| anyhow!("Unable to read wasm binary for wasm-opt optimizations"))?;
     module.optimize(&codegen_cfg);
-    module
-        .run_optimization_passes(vec!["strip"], &codegen_cfg)
-        .map_err(|_| anyhow!("Running wasm-opt optimization passes failed"))?;
+    // The component type section of the incoming handler is needed to turn
+    // the module into a component
+    if !http_proxy {
+        module
+            .run_optimization_passes(vec!["strip"], &codegen_cfg)
+            .map_err(|_| anyhow!("Running wasm-opt optimization passes failed"))?;
+    }
     let wasm = module.write();
 
     // This increases the size of the binary, which is not needed now
@@ -148,6 +202,42 @@ pub fn generate(js: &JS, exports: Vec<Export>, fpermissions: &Option<PathBuf>, h
     //Ok(wasm)
 }
 
+/// The function imports of the engine stubbed during pre-initialization, read
+/// from its import section.
+fn runtime_only_imports(wasm: &[u8], http_proxy: bool) -> Result<Vec<(String, String, FuncType)>> {
+    let engine = walrus::Module::from_buffer(wasm)?;
+    let mut imports = Vec::new();
+    for import in engine.imports.iter() {
+        let module = import.module.as_str();
+        if !http_proxy && module.starts_with("wasi:http/") {
+            return Err(anyhow!("The engine is built for the http-proxy world, compile with --world http-proxy"));
+        }
+        let runtime_only = RUNTIME_IMPORTS.contains(&module)
+            || (http_proxy && HTTP_PROXY_IMPORTS.iter().any(|prefix| module.starts_with(prefix)));
+        if let (true, ImportKind::Function(func)) = (runtime_only, &import.kind) {
+            let ty = engine.types.get(engine.funcs.get(*func).ty());
+            let ty = FuncType::new(
+                ty.params().iter().map(to_wasmtime),
+                ty.results().iter().map(to_wasmtime),
+            );
+            imports.push((import.module.clone(), import.name.clone(), ty));
+        }
+    }
+    Ok(imports)
+}
+
+fn to_wasmtime(ty: &ValType) -> wasmtime::ValType {
+    match ty {
+        ValType::I32 => wasmtime::ValType::I32,
+        ValType::I64 => wasmtime::ValType::I64,
+        ValType::F32 => wasmtime::ValType::F32,
+        ValType::F64 => wasmtime::ValType::F64,
+        ValType::V128 => wasmtime::ValType::V128,
+        ValType::Externref => wasmtime::ValType::ExternRef,
+        ValType::Funcref => wasmtime::ValType::FuncRef,
+    }
+}
+
 fn export_exported_js_functions(
     module: &mut walrus::Module,
     realloc_fn: FunctionId,
@@ -0,0 +1,162 @@
+use anyhow::{anyhow, bail, Result};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `wasmtime serve` running the component, killed when dropped.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(other, _)| other.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[test]
+#[ignore = "needs an engine built for the http-proxy world, see `make test-cli-http-proxy`"]
+fn test_http_proxy() -> Result<()> {
+    let adapter = std::env::var("JAVY_PROXY_ADAPTER")
+        .expect("JAVY_PROXY_ADAPTER is not set, see `make test-cli-http-proxy`");
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let tempdir = tempfile::tempdir()?;
+    let module = tempdir.path().join("handler.wasm");
+    let component = tempdir.path().join("handler.component.wasm");
+
+    run(Command::new(env!("CARGO_BIN_EXE_javy"))
+        .current_dir(&root)
+        .args([
+            "compile",
+            "tests/sample-scripts/http-proxy.js",
+            "--world",
+            "http-proxy",
+            "-o",
+        ])
+        .arg(&module))?;
+    run(Command::new("wasm-tools")
+        .args(["component", "new"])
+        .arg(&module)
+        .arg("--adapt")
+        .arg(&adapter)
+        .arg("-o")
+        .arg(&component))?;
+
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let _server = Server(
+        Command::new("wasmtime")
+            .arg("serve")
+            .arg(format!("--addr=127.0.0.1:{port}"))
+            .arg(&component)
+            .spawn()?,
+    );
+
+    let response = send(port, "POST", "world")?;
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+    assert_eq!(response.header("x-method"), Some("POST"));
+    assert_eq!(str::from_utf8(&response.body)?, "Hello world!");
+
+    let response = send(port, "GET", "")?;
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("x-method"), Some("GET"));
+    assert_eq!(str::from_utf8(&response.body)?, "Hello !");
+
+    // Errors of the handler are answered by the host
+    let response = send(port, "DELETE", "")?;
+    assert_eq!(response.status, 500);
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let output = command.output()?;
+    if !output.status.success() {
+        bail!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// Sends a request once the server accepts connections.
+fn send(port: u16, method: &str, body: &str) -> Result<Response> {
+    let started = Instant::now();
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                thread::sleep(Duration::from_millis(100))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    write!(
+        stream,
+        "{method} /greet HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data)?;
+
+    let head_end = find(&data, b"\r\n\r\n")?;
+    let head = str::from_utf8(&data[..head_end])?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .ok_or_else(|| anyhow!("Invalid status line"))?
+        .parse()?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = Response {
+        status,
+        headers,
+        body: data[head_end + 4..].to_vec(),
+    };
+    if response.header("transfer-encoding") == Some("chunked") {
+        response.body = dechunk(&response.body)?;
+    }
+    Ok(response)
+}
+
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = find(data, b"\r\n")?;
+        let size = usize::from_str_radix(str::from_utf8(&data[..line_end])?.trim(), 16)?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Result<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+        .ok_or_else(|| anyhow!("Truncated HTTP response"))
+}
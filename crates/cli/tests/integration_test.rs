@@ -1,8 +1,15 @@
 mod common;
 mod runner;
 
+use javy_node_red_host::Event;
 use runner::{Runner, RunnerError};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 #[test]
 fn test_identity() {
@@ -149,7 +156,6 @@ fn test_producers_section_present() {
     common::assert_producers_section_is_correct(&runner.wasm).unwrap();
 }
 
-
 #[test]
 fn test_fs() {
     let runner = Runner::new("fs.js");
@@ -159,6 +165,67 @@ fn test_fs() {
     assert!(std::path::Path::new("test.txt").exists());
 }
 
+#[test]
+fn test_file_and_http_permissions() {
+    let mut runner = Runner::new_with_permissions(
+        "permissions.js",
+        "permissions-fs.yaml",
+        "permissions-http.yaml",
+    );
+
+    let (_, logs, _) = run(&mut runner, &[]);
+    assert_eq!(
+        logs,
+        "EACCES,EROFS,200,GET https://api.example.com/v1/items,Access denied: GET http://api.example.com/v1/items,ERR_HTTP_REQUEST_TOO_LARGE\n"
+    );
+}
+
+#[test]
+fn test_http_forwarded_to_server() {
+    let (origin, served) = start_echo_server();
+    let mut runner = Runner::new_with_permissions(
+        "http-forward.js",
+        "permissions-fs.yaml",
+        "permissions-http-forward.yaml",
+    );
+    runner.forward_http();
+
+    let (_, logs, _) = run(&mut runner, origin.as_bytes());
+    assert_eq!(logs, "200,yes,POST /echo yes hello,EACCES\n");
+    // The denied request never reached the server
+    assert_eq!(served.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_embed_dir() {
+    let mut runner =
+        Runner::new_with_embed_dir("embed-dir.js", "embedded:/assets", "permissions-embed.yaml");
+    // The host preopens another directory than the one embedded at build time
+    let host_dir = tempfile::tempdir().unwrap();
+    std::fs::write(host_dir.path().join("greeting.txt"), "host").unwrap();
+    runner.preopen_dir(host_dir.path(), "/host");
+
+    let (_, logs, _) = run(&mut runner, &[]);
+    assert_eq!(logs, "embedded,host\n");
+}
+
+#[test]
+fn test_node_red() {
+    let mut runner = Runner::new("node-red.js");
+
+    let (events, _) = runner
+        .exec_node_red(vec![json!({ "payload": 21 })])
+        .unwrap();
+    assert_eq!(
+        events,
+        vec![
+            Event::Send(json!({ "payload": 42 })),
+            Event::Warn(json!("no more messages")),
+            Event::Done(serde_json::Value::Null),
+        ]
+    );
+}
+
 #[test]
 fn test_error_handling() {
     let mut runner = Runner::new("error.js");
@@ -205,6 +272,56 @@ fn test_exported_default_fn() {
     assert_fuel_consumed_within_threshold(49_748, fuel_consumed);
 }
 
+/// Starts a server answering `METHOD PATH X-TEST BODY` with an `x-echo: yes`
+/// header, returns its origin and the number of requests it served.
+fn start_echo_server() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let origin = format!("http://{}", listener.local_addr().unwrap());
+    let served = Arc::new(AtomicUsize::new(0));
+    let counter = served.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let (mut x_test, mut content_length) = (String::new(), 0);
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let Some((name, value)) = line.trim_end().split_once(':') else {
+                    break;
+                };
+                match name.to_ascii_lowercase().as_str() {
+                    "x-test" => x_test = value.trim().to_string(),
+                    "content-length" => content_length = value.trim().parse().unwrap(),
+                    _ => {}
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+
+            let mut parts = request_line.split(' ');
+            let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+            let body = format!(
+                "{} {} {} {}",
+                method,
+                path,
+                x_test,
+                str::from_utf8(&body).unwrap()
+            );
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\nx-echo: yes\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+    (origin, served)
+}
+
 fn run_with_u8s(r: &mut Runner, stdin: u8) -> (u8, String, u64) {
     let (output, logs, fuel_consumed) = run(r, &stdin.to_le_bytes());
     assert_eq!(1, output.len());
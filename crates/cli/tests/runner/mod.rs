@@ -1,19 +1,24 @@
 use anyhow::Result;
+use javy_node_red_host::{Event, NodeRedCtx, Recorder};
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
-use std::io::{self, Cursor, Write};
+use std::io::{self, Cursor, Read, Write};
+use std::net::TcpStream;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{cmp, fs};
 use wasi_common::pipe::{ReadPipe, WritePipe};
-use wasmtime::{Config, Engine, Linker, Module, OptLevel, Store};
-use wasmtime_wasi::sync::WasiCtxBuilder;
+use wasmtime::{Caller, Config, Engine, Linker, Module, OptLevel, Store};
+use wasmtime_wasi::sync::{ambient_authority, Dir, WasiCtxBuilder};
 use wasmtime_wasi::WasiCtx;
 
 pub struct Runner {
     pub wasm: Vec<u8>,
     linker: Linker<StoreContext>,
     log_capacity: usize,
+    // Host directories preopened at a guest path when running the module
+    preopens: Vec<(PathBuf, String)>,
+    forward_http: bool,
 }
 
 #[derive(Debug)]
@@ -39,22 +44,39 @@ struct StoreContext {
     wasi_output: WritePipe<Cursor<Vec<u8>>>,
     wasi: WasiCtx,
     log_stream: WritePipe<LogWriter>,
+    // Encoded response of the last `javy_http_v1::request`
+    http_response: Vec<u8>,
+    forward_http: bool,
+    node_red: NodeRedCtx<Recorder>,
 }
 
 impl StoreContext {
-    fn new(input: &[u8], capacity: usize) -> Self {
+    fn new(
+        input: &[u8],
+        capacity: usize,
+        node_red: NodeRedCtx<Recorder>,
+        preopens: &[(PathBuf, String)],
+        forward_http: bool,
+    ) -> Result<Self> {
         let wasi_output = WritePipe::new_in_memory();
         let log_stream = WritePipe::new(LogWriter::new(capacity));
-        let wasi = WasiCtxBuilder::new()
+        let mut wasi = WasiCtxBuilder::new()
             .stdout(Box::new(wasi_output.clone()))
             .stdin(Box::new(ReadPipe::from(input)))
-            .stderr(Box::new(log_stream.clone()))
-            .build();
-        Self {
+            .stderr(Box::new(log_stream.clone()));
+        for (host_path, guest_path) in preopens {
+            let dir = Dir::open_ambient_dir(host_path, ambient_authority())?;
+            wasi = wasi.preopened_dir(dir, guest_path)?;
+        }
+        let wasi = wasi.build();
+        Ok(Self {
             wasi,
             wasi_output,
             log_stream,
-        }
+            http_response: Vec::new(),
+            forward_http,
+            node_red,
+        })
     }
 }
 
@@ -66,7 +88,60 @@ impl Default for Runner {
 
 impl Runner {
     pub fn new(js_file: impl AsRef<Path>) -> Self {
-        Self::new_with_fixed_logging_capacity(js_file, None, None, usize::MAX)
+        Self::new_with_fixed_logging_capacity(js_file, None, None, &[], usize::MAX)
+    }
+
+    /// Compiles with the file and http permission files of the sample
+    /// scripts directory.
+    pub fn new_with_permissions(
+        js_file: impl AsRef<Path>,
+        file_permissions: impl AsRef<Path>,
+        http_permissions: impl AsRef<Path>,
+    ) -> Self {
+        let sample_scripts = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("tests")
+            .join("sample-scripts");
+        let args = [
+            "--file-permissions".to_string(),
+            sample_scripts
+                .join(file_permissions)
+                .to_str()
+                .unwrap()
+                .to_string(),
+            "--http-permissions".to_string(),
+            sample_scripts
+                .join(http_permissions)
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ];
+        Self::new_with_fixed_logging_capacity(js_file, None, None, &args, usize::MAX)
+    }
+
+    /// Compiles with `--embed-dir` and the file permissions of the sample
+    /// scripts directory, `embed_dir` is `<host_path>:<virtual_path>` with
+    /// the host path relative to the sample scripts.
+    pub fn new_with_embed_dir(
+        js_file: impl AsRef<Path>,
+        embed_dir: &str,
+        file_permissions: impl AsRef<Path>,
+    ) -> Self {
+        let sample_scripts = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("tests")
+            .join("sample-scripts");
+        let (host_path, virtual_path) = embed_dir.rsplit_once(':').unwrap();
+        let host_path = sample_scripts.join(host_path);
+        let args = [
+            "--embed-dir".to_string(),
+            format!("{}:{}", host_path.display(), virtual_path),
+            "--file-permissions".to_string(),
+            sample_scripts
+                .join(file_permissions)
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ];
+        Self::new_with_fixed_logging_capacity(js_file, None, None, &args, usize::MAX)
     }
 
     pub fn new_with_exports(
@@ -78,6 +153,7 @@ impl Runner {
             js_file,
             Some(wit_path.as_ref()),
             Some(world),
+            &[],
             usize::MAX,
         )
     }
@@ -86,6 +162,7 @@ impl Runner {
         js_file: impl AsRef<Path>,
         wit_path: Option<&Path>,
         wit_world: Option<&str>,
+        extra_args: &[String],
         capacity: usize,
     ) -> Self {
         let wasm_file_name = format!("{}.wasm", uuid::Uuid::new_v4());
@@ -114,6 +191,7 @@ impl Runner {
             args.push("-n".to_string());
             args.push(world.to_string());
         }
+        args.extend_from_slice(extra_args);
 
         let output = Command::new(env!("CARGO_BIN_EXE_javy"))
             .current_dir(root)
@@ -137,21 +215,68 @@ impl Runner {
             wasm,
             linker,
             log_capacity: capacity,
+            preopens: vec![],
+            forward_http: false,
         }
     }
 
+    /// Sends the `javy_http_v1` requests of the next executions to their
+    /// URL, which must be an `http://` URL of a local server, instead of
+    /// answering them in-process.
+    pub fn forward_http(&mut self) {
+        self.forward_http = true;
+    }
+
+    /// Preopens `host_path` at `guest_path` for the next executions.
+    pub fn preopen_dir(&mut self, host_path: impl AsRef<Path>, guest_path: &str) {
+        self.preopens
+            .push((host_path.as_ref().to_path_buf(), guest_path.to_string()));
+    }
+
     pub fn exec(&mut self, input: &[u8]) -> Result<(Vec<u8>, Vec<u8>, u64)> {
         self.exec_func("_start", input)
     }
 
     pub fn exec_func(&mut self, func: &str, input: &[u8]) -> Result<(Vec<u8>, Vec<u8>, u64)> {
+        let node_red = NodeRedCtx::new(Recorder::default());
+        self.exec_with(func, input, node_red)
+            .map(|(output, logs, fuel_consumed, _)| (output, logs, fuel_consumed))
+    }
+
+    /// Runs the module as a Node-RED node with `inputs` queued, returns the
+    /// calls it made to the node and its logs.
+    pub fn exec_node_red(
+        &mut self,
+        inputs: Vec<serde_json::Value>,
+    ) -> Result<(Vec<Event>, Vec<u8>)> {
+        let mut node_red = NodeRedCtx::new(Recorder::default());
+        for input in inputs {
+            node_red.push_input(input);
+        }
+        self.exec_with("_start", &[], node_red)
+            .map(|(_, logs, _, events)| (events, logs))
+    }
+
+    fn exec_with(
+        &mut self,
+        func: &str,
+        input: &[u8],
+        node_red: NodeRedCtx<Recorder>,
+    ) -> Result<(Vec<u8>, Vec<u8>, u64, Vec<Event>)> {
         let mut store = Store::new(
             self.linker.engine(),
-            StoreContext::new(input, self.log_capacity),
+            StoreContext::new(
+                input,
+                self.log_capacity,
+                node_red,
+                &self.preopens,
+                self.forward_http,
+            )?,
         );
         store.add_fuel(u64::MAX)?;
 
         let module = Module::from_binary(self.linker.engine(), &self.wasm)?;
+        javy_node_red_host::check_module(&module)?;
 
         let instance = self.linker.instantiate(&mut store, &module)?;
         let run = instance.get_typed_func::<(), ()>(&mut store, func)?;
@@ -160,6 +285,7 @@ impl Runner {
         let fuel_consumed = store.fuel_consumed().unwrap();
         let store_context = store.into_data();
         drop(store_context.wasi);
+        let events = store_context.node_red.into_host().events;
         let logs = store_context
             .log_stream
             .try_into_inner()
@@ -172,7 +298,7 @@ impl Runner {
             .into_inner();
 
         match res {
-            Ok(_) => Ok((output, logs, fuel_consumed)),
+            Ok(_) => Ok((output, logs, fuel_consumed, events)),
             Err(err) => Err(RunnerError {
                 stdout: output,
                 stderr: logs,
@@ -195,10 +321,152 @@ fn setup_linker(engine: &Engine) -> Linker<StoreContext> {
 
     wasmtime_wasi::sync::add_to_linker(&mut linker, |ctx: &mut StoreContext| &mut ctx.wasi)
         .expect("failed to add wasi context");
+    add_http_to_linker(&mut linker).expect("failed to add http host functions");
+    javy_node_red_host::add_to_linker(&mut linker, |ctx: &mut StoreContext| &mut ctx.node_red)
+        .expect("failed to add node-red host functions");
 
     linker
 }
 
+/// The `javy_http_v1` host functions. Unless the runner forwards requests,
+/// every request succeeds with a `200` whose body is the method and URL of
+/// the request.
+fn add_http_to_linker(linker: &mut Linker<StoreContext>) -> Result<()> {
+    linker.func_wrap(
+        "javy_http_v1",
+        "request",
+        |mut caller: Caller<'_, StoreContext>, data: i32, length: i32| -> Result<i32> {
+            let memory = caller
+                .get_export("memory")
+                .and_then(|export| export.into_memory())
+                .ok_or_else(|| anyhow::anyhow!("missing memory export"))?;
+            let mut request = vec![0; length as usize];
+            memory.read(&caller, data as usize, &mut request)?;
+
+            let head_length = u32::from_le_bytes(request[..4].try_into()?) as usize;
+            let head: serde_json::Value = serde_json::from_slice(&request[4..4 + head_length])?;
+            let (head, body) = if caller.data().forward_http {
+                forward_http(&head, &request[4 + head_length..])
+                    .unwrap_or_else(|err| (serde_json::json!({ "error": err.to_string() }), vec![]))
+            } else {
+                let body = format!(
+                    "{} {}",
+                    head["method"].as_str().unwrap_or_default(),
+                    head["url"].as_str().unwrap_or_default()
+                );
+                let head = serde_json::json!({
+                    "status": 200,
+                    "headers": [["content-type", "text/plain"]],
+                });
+                (head, body.into_bytes())
+            };
+            let head = head.to_string();
+
+            let response = &mut caller.data_mut().http_response;
+            response.clear();
+            response.extend_from_slice(&(head.len() as u32).to_le_bytes());
+            response.extend_from_slice(head.as_bytes());
+            response.extend_from_slice(&body);
+            Ok(response.len() as i32)
+        },
+    )?;
+    linker.func_wrap(
+        "javy_http_v1",
+        "response",
+        |mut caller: Caller<'_, StoreContext>, data: i32, length: i32| -> Result<()> {
+            let memory = caller
+                .get_export("memory")
+                .and_then(|export| export.into_memory())
+                .ok_or_else(|| anyhow::anyhow!("missing memory export"))?;
+            let response = std::mem::take(&mut caller.data_mut().http_response);
+            memory.write(&mut caller, data as usize, &response[..length as usize])?;
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// Makes the request of the decoded `head` over HTTP/1.1, returns the head
+/// and body of the response.
+fn forward_http(head: &serde_json::Value, body: &[u8]) -> Result<(serde_json::Value, Vec<u8>)> {
+    let url = head["url"].as_str().unwrap_or_default();
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow::anyhow!("only http:// URLs are forwarded: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        head["method"].as_str().unwrap_or("GET"),
+        path,
+        authority,
+        body.len()
+    );
+    for header in head["headers"].as_array().into_iter().flatten() {
+        let name = header[0].as_str().unwrap_or_default();
+        if !name.eq_ignore_ascii_case("host") && !name.eq_ignore_ascii_case("content-length") {
+            request.push_str(&format!(
+                "{}: {}\r\n",
+                name,
+                header[1].as_str().unwrap_or_default()
+            ));
+        }
+    }
+    request.push_str("\r\n");
+
+    let mut stream = TcpStream::connect(authority)?;
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let head_end = find(&response, b"\r\n\r\n")?;
+    let mut lines = std::str::from_utf8(&response[..head_end])?.split("\r\n");
+    let status: u16 = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .ok_or_else(|| anyhow::anyhow!("invalid status line"))?
+        .parse()?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let mut body = response[head_end + 4..].to_vec();
+    if headers
+        .iter()
+        .any(|(name, value)| name == "transfer-encoding" && value == "chunked")
+    {
+        body = dechunk(&body)?;
+    }
+    Ok((
+        serde_json::json!({ "status": status, "headers": headers }),
+        body,
+    ))
+}
+
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = find(data, b"\r\n")?;
+        let size = usize::from_str_radix(std::str::from_utf8(&data[..line_end])?.trim(), 16)?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Result<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+        .ok_or_else(|| anyhow::anyhow!("truncated HTTP response"))
+}
+
 #[derive(Debug)]
 pub struct LogWriter {
     pub buffer: Vec<u8>,
@@ -0,0 +1,3 @@
+const embedded = Node.FS.readFileSync("/assets/greeting.txt", "utf8");
+const host = Node.FS.readFileSync("/host/greeting.txt", "utf8");
+console.log(`${embedded},${host}`);
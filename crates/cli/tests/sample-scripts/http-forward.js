@@ -0,0 +1,12 @@
+const buffer = new Uint8Array(1024);
+const n = Javy.IO.readSync(0, buffer);
+const origin = new TextDecoder().decode(buffer.subarray(0, n));
+const results = [];
+const res = Node.HTTP.request(`${origin}/echo`, "hello", { "x-test": "yes" }, "POST");
+results.push(res.status, res.headers["x-echo"], new TextDecoder().decode(res.body));
+try {
+  Node.HTTP.request(`${origin}/secret`, undefined, {}, "GET");
+} catch (e) {
+  results.push(e.code);
+}
+console.log(results.join(","));
@@ -0,0 +1,18 @@
+async function* greeting(name) {
+  yield "Hello ";
+  yield new TextEncoder().encode(name);
+  yield "!";
+}
+
+export default {
+  async fetch(request) {
+    if (request.method === "DELETE") {
+      throw new Error("Nothing to delete");
+    }
+    const name = await request.text();
+    return new Response(greeting(name), {
+      status: 201,
+      headers: { "content-type": "text/plain", "x-method": request.method },
+    });
+  },
+};
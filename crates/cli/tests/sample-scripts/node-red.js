@@ -0,0 +1,7 @@
+const msg = Node.IO.msg();
+Node.IO.send({ payload: msg.payload * 2 });
+Node.IO.pop();
+if (Node.IO.msg() === null) {
+    Node.IO.warn("no more messages");
+}
+Node.IO.done();
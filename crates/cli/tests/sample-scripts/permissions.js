@@ -0,0 +1,24 @@
+const results = [];
+try {
+  Node.FS.readFileSync("/secret.txt");
+} catch (e) {
+  results.push(e.code);
+}
+try {
+  Node.FS.writeFileSync("/data/out.txt", "data");
+} catch (e) {
+  results.push(e.code);
+}
+const res = Node.HTTP.request("https://api.example.com/v1/items", undefined, {}, "GET");
+results.push(res.status, new TextDecoder().decode(res.body));
+try {
+  Node.HTTP.request("http://api.example.com/v1/items", undefined, {}, "GET");
+} catch (e) {
+  results.push(e.message);
+}
+try {
+  Node.HTTP.request("https://api.example.com/v1/items", "x".repeat(17), {}, "GET");
+} catch (e) {
+  results.push(e.code);
+}
+console.log(results.join(","));
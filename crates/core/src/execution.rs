@@ -25,7 +25,7 @@ pub fn invoke_function(runtime: &Runtime, fn_module: &str, fn_name: &str) {
         .unwrap_or_else(handle_error);
 }
 
-fn process_event_loop(context: &JSContextRef) -> Result<()> {
+pub fn process_event_loop(context: &JSContextRef) -> Result<()> {
     if cfg!(feature = "experimental_event_loop") {
         context.execute_pending()?;
     } else if context.is_pending() {
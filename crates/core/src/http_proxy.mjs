@@ -0,0 +1,71 @@
+// Evaluated once by `http_proxy.rs`, which then calls `handle` with each
+// incoming request and `next` until the body of the response is sent. Both
+// leave what their promise settles to in `settled`: the status and headers
+// of the response returned by the default export of the function module,
+// then each chunk of its body, or the error.
+import handler from "function.mjs";
+
+const proxy = {
+    settled: undefined,
+    chunks: null,
+
+    handle(incoming) {
+        this.chunks = null;
+        this.settle(
+            new Promise((resolve) => resolve(callHandler(incoming))).then((response) => {
+                if (!(response instanceof Response)) {
+                    throw new TypeError("The handler must return a Response");
+                }
+                if (response.body !== null) {
+                    this.chunks = response.body[Symbol.asyncIterator]();
+                }
+                return { status: response.status, headers: [...response.headers] };
+            })
+        );
+    },
+
+    next() {
+        if (this.chunks === null) {
+            this.settled = { done: true };
+            return;
+        }
+        this.settle(
+            this.chunks.next().then(({ done, value }) => {
+                if (done) {
+                    this.chunks = null;
+                    return { done: true };
+                }
+                return { done: false, chunk: value.buffer.slice(value.byteOffset, value.byteOffset + value.byteLength) };
+            })
+        );
+    },
+
+    settle(promise) {
+        promise.then(
+            (value) => {
+                this.settled = value;
+            },
+            (e) => {
+                this.chunks = null;
+                const error = e instanceof Error ? `${e.name}: ${e.message}` : String(e);
+                this.settled = { error };
+            }
+        );
+    },
+};
+
+function callHandler(incoming) {
+    const fetchHandler = typeof handler === "function" ? handler : handler && handler.fetch;
+    if (typeof fetchHandler !== "function") {
+        throw new TypeError("The default export must be a function or an object with a fetch method");
+    }
+    const hasBody = incoming.body.byteLength > 0 && incoming.method !== "GET" && incoming.method !== "HEAD";
+    const request = new Request(incoming.url, {
+        method: incoming.method,
+        headers: incoming.headers,
+        body: hasBody ? incoming.body : null,
+    });
+    return fetchHandler.call(handler, request);
+}
+
+globalThis.__javyHttpProxy = proxy;
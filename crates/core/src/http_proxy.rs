@@ -0,0 +1,234 @@
+//! `wasi:http/incoming-handler` export, built with the `http-proxy` feature
+//! for modules compiled with `javy compile --world http-proxy`.
+//!
+//! Each incoming request is read whole and handed to the default export of
+//! the JS module as a `Request`. Once the event loop is done, the status and
+//! headers of the `Response` it settles to are sent back, then its body one
+//! chunk at a time, running the event loop for each chunk of a streamed
+//! body. Errors of the handler are sent to the host as an internal error,
+//! which it answers with a 500, errors while sending the body cut it short.
+use anyhow::{anyhow, bail, Result};
+use javy::quickjs::{JSContextRef, JSValueRef};
+use wasi::exports::http::incoming_handler::Guest;
+use wasi::http::types::{
+    ErrorCode, Fields, IncomingBody, IncomingRequest, Method, OutgoingBody, OutgoingResponse,
+    ResponseOutparam, Scheme,
+};
+use wasi::io::streams::StreamError;
+
+use crate::{execution, runtime, WasmCtx, RUNTIME};
+
+/// Bytes read from or written to a stream at a time.
+const CHUNK_SIZE: usize = 4096;
+
+const PROXY_MODULE_NAME: &str = "http_proxy.mjs";
+const PROXY_MODULE: &str = include_str!("http_proxy.mjs");
+/// Global set by `http_proxy.mjs` when it is evaluated.
+const PROXY_GLOBAL: &str = "__javyHttpProxy";
+
+struct HttpProxy;
+
+wasi::http::proxy::export!(HttpProxy);
+
+struct Incoming {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Guest for HttpProxy {
+    fn handle(request: IncomingRequest, response_out: ResponseOutparam) {
+        let _wasm_ctx = WasmCtx::new();
+        runtime::reset_invocation();
+        let context = unsafe { RUNTIME[0].context() };
+        let response = proxy(context).and_then(|proxy| {
+            let incoming = read_request(request)?;
+            Ok((call_handler(context, &proxy, incoming)?, proxy))
+        });
+        match response {
+            Ok((response, proxy)) => {
+                let outgoing_body = response.body().expect("The response body is taken once");
+                ResponseOutparam::set(response_out, Ok(response));
+                // The status and headers are sent, an error can only cut the
+                // body short
+                if let Err(e) = write_body(context, &proxy, outgoing_body) {
+                    eprintln!("Error while sending the response: {e}");
+                }
+            }
+            Err(e) => {
+                eprintln!("Error while handling the request: {e}");
+                ResponseOutparam::set(
+                    response_out,
+                    Err(ErrorCode::InternalError(Some(e.to_string()))),
+                );
+            }
+        }
+        runtime::reset_invocation();
+    }
+}
+
+fn read_request(request: IncomingRequest) -> Result<Incoming> {
+    let method = match request.method() {
+        Method::Get => "GET".to_string(),
+        Method::Head => "HEAD".to_string(),
+        Method::Post => "POST".to_string(),
+        Method::Put => "PUT".to_string(),
+        Method::Delete => "DELETE".to_string(),
+        Method::Connect => "CONNECT".to_string(),
+        Method::Options => "OPTIONS".to_string(),
+        Method::Trace => "TRACE".to_string(),
+        Method::Patch => "PATCH".to_string(),
+        Method::Other(other) => other,
+    };
+    let scheme = match request.scheme() {
+        Some(Scheme::Https) => "https".to_string(),
+        Some(Scheme::Other(other)) => other,
+        Some(Scheme::Http) | None => "http".to_string(),
+    };
+    let authority = request
+        .authority()
+        .unwrap_or_else(|| "localhost".to_string());
+    let path_with_query = request.path_with_query().unwrap_or_else(|| "/".to_string());
+    let headers = request
+        .headers()
+        .entries()
+        .into_iter()
+        .map(|(name, value)| (name, String::from_utf8_lossy(&value).into_owned()))
+        .collect();
+
+    let incoming_body = request
+        .consume()
+        .map_err(|_| anyhow!("Request body already taken"))?;
+    let mut body = Vec::new();
+    {
+        let stream = incoming_body
+            .stream()
+            .map_err(|_| anyhow!("Request body stream already taken"))?;
+        loop {
+            match stream.blocking_read(CHUNK_SIZE as u64) {
+                Ok(chunk) => body.extend_from_slice(&chunk),
+                Err(StreamError::Closed) => break,
+                Err(e) => bail!("Could not read the request body: {:?}", e),
+            }
+        }
+    }
+    drop(IncomingBody::finish(incoming_body));
+
+    Ok(Incoming {
+        method,
+        url: format!("{scheme}://{authority}{path_with_query}"),
+        headers,
+        body,
+    })
+}
+
+/// The object of `http_proxy.mjs`, which is evaluated on the first request
+/// only.
+fn proxy(context: &JSContextRef) -> Result<JSValueRef> {
+    let global = context.global_object()?;
+    let proxy = global.get_property(PROXY_GLOBAL)?;
+    if !proxy.is_undefined() {
+        return Ok(proxy);
+    }
+    context.eval_module(PROXY_MODULE_NAME, PROXY_MODULE)?;
+    global.get_property(PROXY_GLOBAL)
+}
+
+/// Calls `method` of the proxy, runs the event loop and returns what the
+/// promise of the call settled to.
+fn settle<'a>(
+    context: &'a JSContextRef,
+    proxy: &JSValueRef<'a>,
+    method: &str,
+    args: &[JSValueRef<'a>],
+) -> Result<JSValueRef<'a>> {
+    proxy.get_property(method)?.call(proxy, args)?;
+    execution::process_event_loop(context)?;
+
+    let settled = proxy.get_property("settled")?;
+    proxy.set_property("settled", context.undefined_value()?)?;
+    if settled.is_undefined() {
+        bail!("The handler never settled its response");
+    }
+    let error = settled.get_property("error")?;
+    if !error.is_undefined() {
+        bail!("{}", error.as_str_lossy());
+    }
+    Ok(settled)
+}
+
+/// Hands `incoming` to the handler and returns the status and headers of the
+/// response it settled to, its body is sent by [`write_body`].
+fn call_handler(
+    context: &JSContextRef,
+    proxy: &JSValueRef,
+    incoming: Incoming,
+) -> Result<OutgoingResponse> {
+    let request = context.object_value()?;
+    request.set_property("method", context.value_from_str(&incoming.method)?)?;
+    request.set_property("url", context.value_from_str(&incoming.url)?)?;
+    let headers = context.array_value()?;
+    for (name, value) in &incoming.headers {
+        let header = context.array_value()?;
+        header.append_property(context.value_from_str(name)?)?;
+        header.append_property(context.value_from_str(value)?)?;
+        headers.append_property(header)?;
+    }
+    request.set_property("headers", headers)?;
+    request.set_property("body", context.array_buffer_value(&incoming.body)?)?;
+
+    let response = settle(context, proxy, "handle", &[request])?;
+    let status = response.get_property("status")?.try_as_integer()?;
+    let headers_value = response.get_property("headers")?;
+    let length = headers_value.get_property("length")?.try_as_integer()?;
+    let mut headers = Vec::new();
+    for index in 0..length as u32 {
+        let header = headers_value.get_indexed_property(index)?;
+        headers.push((
+            header.get_indexed_property(0)?.as_str()?.to_string(),
+            header
+                .get_indexed_property(1)?
+                .as_str()?
+                .as_bytes()
+                .to_vec(),
+        ));
+    }
+    new_response(status as u16, &headers)
+}
+
+fn new_response(status: u16, headers: &[(String, Vec<u8>)]) -> Result<OutgoingResponse> {
+    let fields =
+        Fields::from_list(headers).map_err(|e| anyhow!("Invalid response headers: {:?}", e))?;
+    let response = OutgoingResponse::new(fields);
+    response
+        .set_status_code(status)
+        .map_err(|_| anyhow!("Invalid status: {}", status))?;
+    Ok(response)
+}
+
+/// Writes the chunks of the response body as the handler produces them.
+fn write_body(
+    context: &JSContextRef,
+    proxy: &JSValueRef,
+    outgoing_body: OutgoingBody,
+) -> Result<()> {
+    {
+        let stream = outgoing_body
+            .write()
+            .map_err(|_| anyhow!("Response body stream already taken"))?;
+        loop {
+            let next = settle(context, proxy, "next", &[])?;
+            if next.get_property("done")?.as_bool()? {
+                break;
+            }
+            for chunk in next.get_property("chunk")?.as_bytes()?.chunks(CHUNK_SIZE) {
+                stream
+                    .blocking_write_and_flush(chunk)
+                    .map_err(|e| anyhow!("Could not write the response body: {:?}", e))?;
+            }
+        }
+    }
+    OutgoingBody::finish(outgoing_body, None)
+        .map_err(|e| anyhow!("Could not finish the response body: {:?}", e))
+}
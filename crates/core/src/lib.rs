@@ -77,8 +77,10 @@ pub unsafe extern "C" fn invoke(
     fn_name_len: usize,
 ) {
     let runtime = RUNTIME.get().unwrap();
+    runtime::reset_invocation();
     let bytecode = slice::from_raw_parts(bytecode_ptr, bytecode_len);
     let fn_name = str::from_utf8_unchecked(slice::from_raw_parts(fn_name_ptr, fn_name_len));
     execution::run_bytecode(runtime, bytecode);
     execution::invoke_function(runtime, FUNCTION_MODULE_NAME, fn_name);
+    runtime::reset_invocation();
 }
@@ -6,6 +6,8 @@ use std::str;
 use std::string::String;
 
 mod execution;
+#[cfg(feature = "http-proxy")]
+mod http_proxy;
 mod runtime;
 
 const FUNCTION_MODULE_NAME: &str = "function.mjs";
@@ -14,52 +16,34 @@ static mut RUNTIME: Vec<Runtime> = vec![];
 static mut BYTECODE: Vec<u8> = vec![];
 
 
-#[export_name = "wizer.initialize"]
-pub extern "C" fn init() {
-    // Read the file permissions from an env var
-    let file_permissions = std::env::var("FILE_PERMISSIONS");
-    let runtime = match file_permissions {
-        Ok(env) => {
-            //eprintln!("Reading permissions from env var: {}", env);
-            // parse as yaml
-            let file_permissions = serde_yaml::from_str(&env);
-            match file_permissions {
-                Ok(file_permissions) => runtime::new_runtime_with_file_permissions(file_permissions).unwrap(),
-                Err(e) => {
-                    eprintln!("Invalid permissions file: {}", e);
-                    runtime::new_runtime().unwrap()
-                }
-            }
-        
-        }
+/// Reads a permission set in YAML from the env var `name`. A missing or
+/// empty variable means no permissions of that kind.
+fn read_permissions<T: serde::de::DeserializeOwned>(name: &str) -> Option<T> {
+    let env = match std::env::var(name) {
+        Ok(env) if !env.trim().is_empty() => env,
+        Ok(_) => return None,
         Err(e) => {
-            eprintln!("Could not read file permissions from env var: {}", e);
-            runtime::new_runtime().unwrap()
+            eprintln!("Could not read {} from env var: {}", name, e);
+            return None;
         }
     };
-    let _wasm_ctx = WasmCtx::new();
-
-    // Read the http permissions from an env var
-    let http_permissions = std::env::var("HTTP_PERMISSIONS");
-    let runtime = match http_permissions {
-        Ok(env) => {
-            //eprintln!("Reading permissions from env var: {}", env);
-            // parse as yaml
-            let http_permissions = serde_yaml::from_str(&env);
-            match http_permissions {
-                Ok(http_permissions) => runtime::new_runtime_with_http_permissions(http_permissions).unwrap(),
-                Err(e) => {
-                    eprintln!("Invalid permissions http: {}", e);
-                    runtime::new_runtime().unwrap()
-                }
-            }
-        
-        }
+    match serde_yaml::from_str(&env) {
+        Ok(permissions) => Some(permissions),
         Err(e) => {
-            eprintln!("Could not read file permissions from env var: {}", e);
-            runtime::new_runtime().unwrap()
+            eprintln!("Invalid {}: {}", name, e);
+            None
         }
-    };
+    }
+}
+
+#[export_name = "wizer.initialize"]
+pub extern "C" fn init() {
+    let _wasm_ctx = WasmCtx::new();
+
+    // A single runtime carries both the file and the http permissions
+    let file_permissions = read_permissions("FILE_PERMISSIONS");
+    let http_permissions = read_permissions("HTTP_PERMISSIONS");
+    let runtime = runtime::new_runtime_with_permissions(file_permissions, http_permissions).unwrap();
    
     let mut contents = String::new();
     io::stdin().read_to_string(&mut contents).unwrap();
@@ -80,6 +64,7 @@ fn main() {
     //let bytecode = unsafe { BYTECODE };
     //let runtime = unsafe { RUNTIME };
     unsafe { execution::run_bytecode(&RUNTIME[0], &BYTECODE) };
+    runtime::reset_invocation();
 }
 
 // Removed in post-processing.
@@ -92,8 +77,10 @@ fn main() {
 #[export_name = "javy.invoke"]
 pub unsafe extern "C" fn invoke(fn_name_ptr: *mut u8, fn_name_size: usize) {
     let _wasm_ctx = WasmCtx::new();
+    runtime::reset_invocation();
     let js_fn_name = str::from_utf8_unchecked(slice::from_raw_parts(fn_name_ptr, fn_name_size));
     execution::invoke_function(unsafe { &RUNTIME[0] }, FUNCTION_MODULE_NAME, js_fn_name);
+    runtime::reset_invocation();
 }
 
 // RAII abstraction for calling Wasm ctors and dtors for exported non-main functions.
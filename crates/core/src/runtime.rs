@@ -1,7 +1,13 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use javy::{Config, Runtime};
+use javy_apis::fs::{FSConfig, MountMode, WriteLimits};
+use javy_apis::http::config::{
+    HTTPConfig, HeaderPolicy, HttpAccessRule, HttpLimits, HttpMode, RateLimit, RateLimitPolicy, RedirectPolicy,
+};
 use javy_apis::{APIConfig, LogStream, RuntimeExt};
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
 
 #[derive(serde::Deserialize, Debug)]
 pub struct OfTwo {
@@ -9,41 +15,210 @@ pub struct OfTwo {
     pub BLACKLIST: Vec<String>
 }
 
+#[derive(serde::Deserialize, Debug)]
+pub struct Limits {
+    pub MAX_BYTES_PER_INVOCATION: Option<u64>,
+    pub MAX_FILE_SIZE: Option<u64>,
+    pub MAX_FILES_CREATED: Option<u64>
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Scratch {
+    /// Directory preopened by the host for the scratch area, served at
+    /// `/tmp` when it is relative
+    pub PATH: String,
+    pub MAX_BYTES: Option<u64>
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Mount {
+    /// Absolute path seen by JS, matched by the READ and WRITE patterns
+    pub PATH: String,
+    /// Directory preopened by the host that is served under `PATH`
+    pub PREOPEN: String,
+    /// `ro` or `rw`, mounts are read-only unless stated otherwise
+    #[serde(default)]
+    pub MODE: Option<String>
+}
+
 #[derive(serde::Deserialize, Debug)]
 pub struct FilePermissions {
     pub READ: OfTwo,
-    pub WRITE: OfTwo
+    pub WRITE: OfTwo,
+    /// Warn instead of throwing `EACCES` errors on denied operations
+    #[serde(default)]
+    pub QUIET: bool,
+    /// Directory relative paths and patterns are resolved against
+    #[serde(default)]
+    pub ROOT: Option<String>,
+    /// Writes over these limits throw `EDQUOT` errors
+    #[serde(default)]
+    pub LIMITS: Option<Limits>,
+    /// Private area returned by `Node.FS.tmpdir()`, wiped after every invocation
+    #[serde(default)]
+    pub SCRATCH: Option<Scratch>,
+    /// Stable virtual paths mapped to the host preopens
+    #[serde(default)]
+    pub MOUNTS: Vec<Mount>
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct HttpRule {
     pub domain_pattern: String,
     pub endpoint_pattern: String,
-    pub method: String
+    pub method: String,
+    /// Only allow this scheme, e.g. `https`
+    #[serde(default)]
+    pub scheme: Option<String>,
+    /// Only allow this port, the scheme's default port when the URL has none
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Token bucket for the requests this rule allows
+    #[serde(default)]
+    pub rate_limit: Option<HttpRateLimit>,
+    /// Requests this rule allows that can be in flight at once
+    #[serde(default)]
+    pub max_concurrent: Option<u32>
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct HttpRateLimit {
+    pub requests_per_second: f64,
+    /// Requests allowed at once after a quiet period, 1 by default
+    #[serde(default)]
+    pub burst: Option<u32>
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct HttpRequestLimits {
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub max_request_bytes: Option<u64>,
+    #[serde(default)]
+    pub max_response_bytes: Option<u64>,
+    /// `none`, `same-origin` or `follow`, the default
+    #[serde(default)]
+    pub redirect: Option<String>,
+    /// Redirects followed by the `follow` policy
+    #[serde(default)]
+    pub max_redirects: Option<u32>,
+    /// `wait`, the default, or `reject` for requests over a rate limit
+    #[serde(default)]
+    pub rate_limited: Option<String>
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct HttpHeaderPolicy {
+    pub domain_pattern: String,
+    pub endpoint_pattern: String,
+    /// Header values, `${env:NAME}` and `${file:PATH}` being resolved on
+    /// every request
+    #[serde(default)]
+    pub inject: HashMap<String, String>,
+    /// Headers set by JS that are removed
+    #[serde(default)]
+    pub strip: Vec<String>,
+    /// Headers JS can't set
+    #[serde(default)]
+    pub protect: Vec<String>
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct HttpFixtures {
+    /// `live`, `record` or `replay`
+    pub mode: String,
+    /// Fixture file in a directory preopened by the host
+    pub path: String
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct HttpPermissions {
     pub rules: Vec<HttpRule>,
+    /// Requests over these limits reject with typed errors
+    #[serde(default)]
+    pub limits: Option<HttpRequestLimits>,
+    /// Headers added, removed or forbidden by the module instead of JS
+    #[serde(default)]
+    pub headers: Vec<HttpHeaderPolicy>,
+    /// Records the responses to a file or replays them from it
+    #[serde(default)]
+    pub fixtures: Option<HttpFixtures>,
 }
 
-pub(crate) fn new_runtime() -> Result<Runtime> {
+/// Directories preopened by `javy compile --embed-dir`, one virtual path
+/// per line. They are read into memory while the module is pre-initialized.
+const EMBED_DIRS: &str = "EMBED_DIRS";
+
+thread_local! {
+    // Shares the write usage and scratch area of the last runtime created
+    static FS_CONFIG: RefCell<FSConfig> = RefCell::new(FSConfig::default());
+}
+
+/// Resets the write limits and wipes the scratch area of the runtime, at the
+/// start and at the end of every invocation.
+pub(crate) fn reset_invocation() {
+    FS_CONFIG.with(|config| {
+        let config = config.borrow();
+        config.write_usage().reset();
+        if let Err(e) = config.wipe_scratch() {
+            eprintln!("Could not wipe the scratch area: {}", e);
+        }
+    });
+}
+
+fn new_api_config() -> Result<APIConfig> {
     let mut api_config = APIConfig::default();
     api_config.log_stream(LogStream::StdErr);
-    
+
+    if let Ok(dirs) = std::env::var(EMBED_DIRS) {
+        for dir in dirs.lines().filter(|dir| !dir.is_empty()) {
+            // The host directory is preopened at its virtual path
+            api_config.fs.embed_dir(Path::new(dir), dir)?;
+        }
+        // Reading the directories cached the preopens of the build in
+        // wasi-libc, forget them so the snapshot looks up the ones of the
+        // host running the module
+        unsafe { __wasilibc_reset_preopens() };
+    }
+    Ok(api_config)
+}
+
+extern "C" {
+    // Provided by wasi-libc for pre-initializers like Wizer
+    fn __wasilibc_reset_preopens();
+}
+
+fn build_runtime(api_config: APIConfig) -> Result<Runtime> {
+    FS_CONFIG.with(|config| *config.borrow_mut() = api_config.fs.clone());
     Runtime::new_with_apis(Config::default(), api_config)
 }
 
+pub(crate) fn new_runtime() -> Result<Runtime> {
+    let api_config = new_api_config()?;
+    
+    build_runtime(api_config)
+}
+
 
-pub(crate) fn new_runtime_with_file_permissions(
-    permissions: FilePermissions
+/// Builds the runtime with both the FS and the HTTP policy. Without a
+/// permission set, the defaults deny every access of that kind.
+pub(crate) fn new_runtime_with_permissions(
+    file_permissions: Option<FilePermissions>,
+    http_permissions: Option<HttpPermissions>,
 ) -> Result<Runtime> {
-    let mut api_config = APIConfig::default();
-    api_config.log_stream(LogStream::StdErr);
+    let mut api_config = new_api_config()?;
+    if let Some(permissions) = file_permissions {
+        apply_file_permissions(&mut api_config.fs, permissions)?;
+    }
+    if let Some(permissions) = http_permissions {
+        apply_http_permissions(&mut api_config.http, permissions)?;
+    }
 
-    let mut fsconfig = javy_apis::fs::FSConfig::default();
-    eprintln!("File permissions: {:?}", permissions);
-    
+    build_runtime(api_config)
+}
+
+fn apply_file_permissions(fsconfig: &mut FSConfig, permissions: FilePermissions) -> Result<()> {
     for file in permissions.READ.WHITELIST {
         fsconfig.add_to_read_whitelist(&file);
     }
@@ -56,34 +231,92 @@ pub(crate) fn new_runtime_with_file_permissions(
     for file in permissions.WRITE.BLACKLIST {
         fsconfig.add_to_write_blacklist(&file);
     }
-    
-    api_config.fs = fsconfig;
-    
-    Runtime::new_with_apis(Config::default(), api_config)
+    fsconfig.set_quiet(permissions.QUIET);
+    if let Some(root) = &permissions.ROOT {
+        fsconfig.set_root(root);
+    }
+    for mount in &permissions.MOUNTS {
+        let mode = match &mount.MODE {
+            Some(mode) => MountMode::try_from(mode.as_str())?,
+            None => MountMode::ReadOnly,
+        };
+        fsconfig.add_mount(&mount.PATH, &mount.PREOPEN, mode)?;
+    }
+    if let Some(scratch) = &permissions.SCRATCH {
+        fsconfig.set_scratch_dir(&scratch.PATH, scratch.MAX_BYTES)?;
+    }
+    if let Some(limits) = permissions.LIMITS {
+        fsconfig.set_write_limits(WriteLimits {
+            max_bytes_per_invocation: limits.MAX_BYTES_PER_INVOCATION,
+            max_file_size: limits.MAX_FILE_SIZE,
+            max_files_created: limits.MAX_FILES_CREATED,
+        });
+    }
+    Ok(())
 }
 
-
-
-
-pub(crate) fn new_runtime_with_http_permissions(
-    permissions: HttpPermissions
-) -> Result<Runtime> {
-    let mut api_config = APIConfig::default();
-    api_config.log_stream(LogStream::StdErr);
-
-    eprintln!("HTTP permissions: {:?}", permissions);
-    
-
-    //let mut r = vec![];
-    /*for rule in permissions.rules {
-        let httprule = javy_apis::http::config::HttpAccessRule::new(&rule.method, &rule.domain_pattern, &rule.endpoint_pattern);
-        println!("{:?}", httprule);
-
-        r.push(httprule.unwrap());
-    }*/
-    /*api_config.http = HTTPConfig {
-        allowed_rules: HashSet::from_iter(r)
-    };*/
-    
-    Runtime::new_with_apis(Config::default(), api_config)
+fn apply_http_permissions(httpconfig: &mut HTTPConfig, permissions: HttpPermissions) -> Result<()> {
+    for rule in &permissions.rules {
+        let mut httprule = HttpAccessRule::new(&rule.method, &rule.domain_pattern, &rule.endpoint_pattern)
+            .ok_or_else(|| anyhow!("Invalid HTTP rule: {:?}", rule))?;
+        if let Some(scheme) = &rule.scheme {
+            httprule = httprule.with_scheme(scheme);
+        }
+        if let Some(port) = rule.port {
+            httprule = httprule.with_port(port);
+        }
+        if let Some(rate_limit) = &rule.rate_limit {
+            let rate = RateLimit::new(rate_limit.requests_per_second, rate_limit.burst.unwrap_or(1))
+                .ok_or_else(|| anyhow!("Invalid HTTP rate limit: {:?}", rate_limit))?;
+            httprule = httprule.with_rate_limit(rate);
+        }
+        if let Some(max) = rule.max_concurrent {
+            httprule = httprule.with_max_concurrent(max);
+        }
+        httpconfig.add_rule(httprule);
+    }
+    if let Some(limits) = &permissions.limits {
+        let redirect = match (limits.redirect.as_deref(), limits.max_redirects) {
+            (None | Some("follow"), Some(max)) => RedirectPolicy::Follow(max),
+            (Some(redirect), None) => RedirectPolicy::try_from(redirect)?,
+            (Some(redirect), Some(_)) => bail!("max_redirects does not apply to the {} redirect policy", redirect),
+            (None, None) => RedirectPolicy::default(),
+        };
+        httpconfig.set_limits(HttpLimits {
+            timeout_ms: limits.timeout_ms,
+            max_request_bytes: limits.max_request_bytes,
+            max_response_bytes: limits.max_response_bytes,
+            redirect,
+            rate_limited: match limits.rate_limited.as_deref() {
+                Some(policy) => RateLimitPolicy::try_from(policy)?,
+                None => RateLimitPolicy::default(),
+            },
+        });
+    }
+    for policy in &permissions.headers {
+        let mut headerpolicy = HeaderPolicy::new(&policy.domain_pattern, &policy.endpoint_pattern)
+            .ok_or_else(|| anyhow!("Invalid HTTP header policy: {:?}", policy))?;
+        for (name, value) in &policy.inject {
+            headerpolicy = headerpolicy
+                .with_injected(name, value)
+                .ok_or_else(|| anyhow!("Invalid value for the {} header: {}", name, value))?;
+        }
+        for name in &policy.strip {
+            headerpolicy = headerpolicy.with_stripped(name);
+        }
+        for name in &policy.protect {
+            headerpolicy = headerpolicy.with_protected(name);
+        }
+        httpconfig.add_header_policy(headerpolicy);
+    }
+    if let Some(fixtures) = &permissions.fixtures {
+        let mode = match fixtures.mode.as_str() {
+            "live" => HttpMode::Live,
+            "record" => HttpMode::Record(fixtures.path.clone().into()),
+            "replay" => HttpMode::Replay(fixtures.path.clone().into()),
+            mode => bail!("Invalid HTTP fixtures mode: {}, expected live, record or replay", mode),
+        };
+        httpconfig.set_mode(mode);
+    }
+    Ok(())
 }
@@ -1,5 +1,7 @@
 use crate::quickjs::JSContextRef;
 use anyhow::Result;
+use std::cell::RefCell;
+use std::fmt;
 
 use crate::Config;
 
@@ -33,22 +35,51 @@ use crate::Config;
 ///     .unwrap();
 /// context.eval_global("hello.js", "print('hello!');").unwrap();
 /// ```
-#[derive(Debug)]
 pub struct Runtime {
     context: JSContextRef,
+    drop_hooks: RefCell<Vec<Box<dyn FnOnce()>>>,
 }
 
 impl Runtime {
     /// Creates a new [`Runtime`].
     pub fn new(_config: Config) -> Result<Self> {
         let context = JSContextRef::default();
-        Ok(Self { context })
+        Ok(Self {
+            context,
+            drop_hooks: RefCell::new(vec![]),
+        })
     }
 
     /// A reference to a [`JSContextRef`].
     pub fn context(&self) -> &JSContextRef {
         &self.context
     }
+
+    /// Registers a function that will be invoked once when the [`Runtime`]
+    /// is dropped. Functions are invoked in the order they were registered.
+    ///
+    /// Callbacks wrapped with [`JSContextRef::wrap_callback`] are never freed,
+    /// so APIs holding host resources (for example open files) can use this
+    /// to release them.
+    pub fn on_drop(&self, hook: impl FnOnce() + 'static) {
+        self.drop_hooks.borrow_mut().push(Box::new(hook));
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Runtime")
+            .field("context", &self.context)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        for hook in self.drop_hooks.get_mut().drain(..) {
+            hook();
+        }
+    }
 }
 
 impl Default for Runtime {
@@ -58,3 +89,24 @@ impl Default for Runtime {
         Self::new(Config::default()).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Runtime;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_drop_hooks() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let runtime = Runtime::default();
+        for hook in ["first", "second"] {
+            let calls = calls.clone();
+            runtime.on_drop(move || calls.borrow_mut().push(hook));
+        }
+        assert!(calls.borrow().is_empty());
+
+        drop(runtime);
+        assert_eq!(*calls.borrow(), vec!["first", "second"]);
+    }
+}
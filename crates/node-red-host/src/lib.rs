@@ -0,0 +1,549 @@
+//! Host side of the Node-RED API of Javy modules, the `node_red` feature of
+//! javy-apis, for Wasmtime.
+//!
+//! [`add_to_linker`] defines every function the module imports. Messages,
+//! the node and its context are exchanged as JSON: the module asks for the
+//! size of a value, then for the value to be written in a buffer of that
+//! size, and hands over what it sends as a pointer, an offset and a length.
+//!
+//! The functions are imported from `node_red_v1`, the version of the ABI,
+//! which the module also exports as `node_red_abi_version`. [`check_module`]
+//! rejects modules built for another version before they are instantiated.
+//!
+//! ```no_run
+//! use javy_node_red_host::{add_to_linker, NodeRedCtx, Recorder};
+//! use wasmtime::{Engine, Linker, Module, Store};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let engine = Engine::default();
+//! let mut linker: Linker<NodeRedCtx<Recorder>> = Linker::new(&engine);
+//! add_to_linker(&mut linker, |ctx| ctx)?;
+//!
+//! let mut ctx = NodeRedCtx::new(Recorder::default());
+//! ctx.push_input(serde_json::json!({ "payload": 1 }));
+//! let mut store = Store::new(&engine, ctx);
+//! let module = Module::from_file(&engine, "node.wasm")?;
+//! javy_node_red_host::check_module(&module)?;
+//! let instance = linker.instantiate(&mut store, &module)?;
+//! instance.get_typed_func::<(), ()>(&mut store, "default")?.call(&mut store, ())?;
+//! println!("{:?}", store.data().host().events);
+//! # Ok(())
+//! # }
+//! ```
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use std::collections::VecDeque;
+use wasmtime::{
+    AsContextMut, Caller, ExternType, FuncType, Instance, Linker, Memory, Module, ValType,
+};
+
+/// Version of the Node-RED ABI implemented by this crate.
+pub const ABI_VERSION: i32 = 1;
+
+/// Module the Node-RED functions are imported from.
+pub const IMPORT_MODULE: &str = "node_red_v1";
+
+/// Function exported by the module returning its [`ABI_VERSION`].
+pub const ABI_VERSION_EXPORT: &str = "node_red_abi_version";
+
+/// Functions returning the length of a value.
+const SIZE_FUNCTIONS: [&str; 4] = ["msg_size", "pop_size", "node_size", "context_size"];
+/// Functions writing a value in the memory of the module.
+const READ_FUNCTIONS: [&str; 4] = ["msg", "pop", "node", "context"];
+/// Functions reading a value from the memory of the module.
+const WRITE_FUNCTIONS: [&str; 7] = [
+    "send", "warn", "error", "done", "result", "register", "emit",
+];
+
+/// Names of the functions imported from `env` by modules built before the
+/// ABI was versioned.
+fn is_unversioned_import(module: &str, name: &str) -> bool {
+    module == "env" && (name.starts_with("node_red_") || name == "node_emit")
+}
+
+fn expected_type(name: &str) -> Option<FuncType> {
+    let (params, results) = if SIZE_FUNCTIONS.contains(&name) {
+        (vec![], vec![ValType::I32])
+    } else if READ_FUNCTIONS.contains(&name) {
+        (vec![ValType::I32; 3], vec![ValType::I32])
+    } else if WRITE_FUNCTIONS.contains(&name) {
+        (vec![ValType::I32; 3], vec![])
+    } else {
+        return None;
+    };
+    Some(FuncType::new(params, results))
+}
+
+/// Checks the Node-RED imports of `module` before it is instantiated, so a
+/// module built with an incompatible Javy fails here rather than trapping
+/// on its first call.
+pub fn check_module(module: &Module) -> Result<()> {
+    for import in module.imports() {
+        let (module_name, name) = (import.module(), import.name());
+        if is_unversioned_import(module_name, name) {
+            bail!(
+                "The module imports {} from env, it was built with a Javy older than the {} ABI and must be rebuilt",
+                name,
+                IMPORT_MODULE
+            );
+        }
+        if module_name != IMPORT_MODULE {
+            if module_name.starts_with("node_red_v") {
+                bail!(
+                    "The module was built for the {} ABI, this host implements {}",
+                    module_name,
+                    IMPORT_MODULE
+                );
+            }
+            continue;
+        }
+        let Some(expected) = expected_type(name) else {
+            bail!(
+                "The module imports {}::{}, which is not part of the ABI",
+                IMPORT_MODULE,
+                name
+            );
+        };
+        match import.ty() {
+            ExternType::Func(ty) if ty == expected => {}
+            ty => bail!(
+                "The module imports {}::{} as {:?}, expected {:?}",
+                IMPORT_MODULE,
+                name,
+                ty,
+                expected
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Checks the version exported by an instance of a module importing the
+/// Node-RED functions.
+pub fn check_instance(mut store: impl AsContextMut, instance: &Instance) -> Result<()> {
+    let abi_version = instance
+        .get_typed_func::<(), i32>(&mut store, ABI_VERSION_EXPORT)
+        .map_err(|_| anyhow!("The module doesn't export {}", ABI_VERSION_EXPORT))?;
+    let version = abi_version.call(&mut store, ())?;
+    if version != ABI_VERSION {
+        bail!(
+            "The module implements version {} of the Node-RED ABI, this host implements {}",
+            version,
+            ABI_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Receives what the module hands over to Node-RED.
+pub trait NodeRedHost {
+    /// `Node.IO.send(msg)`
+    fn send(&mut self, msg: Value);
+
+    /// `Node.IO.warn(payload)`
+    fn warn(&mut self, payload: Value);
+
+    /// `Node.IO.error(payload)`
+    fn error(&mut self, payload: Value);
+
+    /// `Node.IO.done(payload)`, `Null` when called without an argument.
+    fn done(&mut self, payload: Value);
+
+    /// `Node.IO.set_result(payload)`
+    fn result(&mut self, _payload: Value) {}
+
+    /// `Node.IO.register_type(name, constructor, options)`, the constructor
+    /// stays in the module.
+    fn register_type(&mut self, _name: String, _options: Value) {}
+
+    /// `Node.IO.emit(event, payload)`
+    fn emit(&mut self, _event: String, _payload: Value) {}
+}
+
+/// A call of the module to a [`NodeRedHost`] method.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Send(Value),
+    Warn(Value),
+    Error(Value),
+    Done(Value),
+    Result(Value),
+    Register { name: String, options: Value },
+    Emit { event: String, payload: Value },
+}
+
+/// Keeps every call in order, for tests.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    pub events: Vec<Event>,
+}
+
+impl NodeRedHost for Recorder {
+    fn send(&mut self, msg: Value) {
+        self.events.push(Event::Send(msg));
+    }
+
+    fn warn(&mut self, payload: Value) {
+        self.events.push(Event::Warn(payload));
+    }
+
+    fn error(&mut self, payload: Value) {
+        self.events.push(Event::Error(payload));
+    }
+
+    fn done(&mut self, payload: Value) {
+        self.events.push(Event::Done(payload));
+    }
+
+    fn result(&mut self, payload: Value) {
+        self.events.push(Event::Result(payload));
+    }
+
+    fn register_type(&mut self, name: String, options: Value) {
+        self.events.push(Event::Register { name, options });
+    }
+
+    fn emit(&mut self, event: String, payload: Value) {
+        self.events.push(Event::Emit { event, payload });
+    }
+}
+
+/// The state of a Node-RED node, kept in the data of the store.
+pub struct NodeRedCtx<H> {
+    host: H,
+    inputs: VecDeque<Value>,
+    node: Value,
+    context: Value,
+}
+
+impl<H: NodeRedHost> NodeRedCtx<H> {
+    pub fn new(host: H) -> Self {
+        Self {
+            host,
+            inputs: VecDeque::new(),
+            node: Value::Null,
+            context: Value::Null,
+        }
+    }
+
+    /// Queues an input message. `Node.IO.msg()` returns the first message
+    /// of the queue and `Node.IO.pop()` takes it off, both return `null`
+    /// when the queue is empty.
+    pub fn push_input(&mut self, msg: Value) {
+        self.inputs.push_back(msg);
+    }
+
+    /// What `Node.IO.node()` returns, the configuration of the node.
+    pub fn set_node(&mut self, node: Value) {
+        self.node = node;
+    }
+
+    /// What `Node.IO.context()` returns.
+    pub fn set_context(&mut self, context: Value) {
+        self.context = context;
+    }
+
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+
+    pub fn host_mut(&mut self) -> &mut H {
+        &mut self.host
+    }
+
+    pub fn into_host(self) -> H {
+        self.host
+    }
+
+    fn msg(&self) -> Vec<u8> {
+        encode(self.inputs.front().unwrap_or(&Value::Null))
+    }
+
+    fn pop(&mut self) -> Vec<u8> {
+        encode(&self.inputs.pop_front().unwrap_or(Value::Null))
+    }
+
+    fn node(&self) -> Vec<u8> {
+        encode(&self.node)
+    }
+
+    fn context(&self) -> Vec<u8> {
+        encode(&self.context)
+    }
+}
+
+fn encode(value: &Value) -> Vec<u8> {
+    // Serializing a `Value` can't fail
+    serde_json::to_vec(value).unwrap_or_default()
+}
+
+/// Defines the Node-RED functions in `linker`, `get` returning the
+/// [`NodeRedCtx`] in the data of the store.
+pub fn add_to_linker<T: 'static, H: NodeRedHost + 'static>(
+    linker: &mut Linker<T>,
+    get: impl Fn(&mut T) -> &mut NodeRedCtx<H> + Send + Sync + Copy + 'static,
+) -> Result<()> {
+    define_reader(linker, "msg_size", "msg", get, NodeRedCtx::msg, |ctx| {
+        ctx.msg()
+    })?;
+    define_reader(
+        linker,
+        "pop_size",
+        "pop",
+        get,
+        NodeRedCtx::msg,
+        NodeRedCtx::pop,
+    )?;
+    define_reader(linker, "node_size", "node", get, NodeRedCtx::node, |ctx| {
+        ctx.node()
+    })?;
+    define_reader(
+        linker,
+        "context_size",
+        "context",
+        get,
+        NodeRedCtx::context,
+        |ctx| ctx.context(),
+    )?;
+
+    define_writer(linker, "send", get, |host, msg| {
+        host.send(msg);
+        Ok(())
+    })?;
+    define_writer(linker, "warn", get, |host, payload| {
+        host.warn(payload);
+        Ok(())
+    })?;
+    define_writer(linker, "error", get, |host, payload| {
+        host.error(payload);
+        Ok(())
+    })?;
+    define_writer(linker, "done", get, |host, payload| {
+        host.done(payload);
+        Ok(())
+    })?;
+    define_writer(linker, "result", get, |host, payload| {
+        host.result(payload);
+        Ok(())
+    })?;
+    define_writer(linker, "register", get, |host, registration| {
+        let Some(name) = registration["name"].as_str() else {
+            bail!("register needs a name");
+        };
+        host.register_type(name.to_string(), registration["options"].clone());
+        Ok(())
+    })?;
+    define_writer(linker, "emit", get, |host, emitted| {
+        let Some(event) = emitted["event"].as_str() else {
+            bail!("emit needs an event");
+        };
+        host.emit(event.to_string(), emitted["payload"].clone());
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Defines `size_name`, returning the length of what `peek` encodes, and
+/// `name`, writing what `take` encodes at `ptr + offset`, up to `length`
+/// bytes, and returning the number of bytes written.
+fn define_reader<T: 'static, H: NodeRedHost + 'static>(
+    linker: &mut Linker<T>,
+    size_name: &str,
+    name: &str,
+    get: impl Fn(&mut T) -> &mut NodeRedCtx<H> + Send + Sync + Copy + 'static,
+    peek: fn(&NodeRedCtx<H>) -> Vec<u8>,
+    take: fn(&mut NodeRedCtx<H>) -> Vec<u8>,
+) -> Result<()> {
+    linker.func_wrap(
+        IMPORT_MODULE,
+        size_name,
+        move |mut caller: Caller<'_, T>| -> i32 { peek(get(caller.data_mut())).len() as i32 },
+    )?;
+    linker.func_wrap(
+        IMPORT_MODULE,
+        name,
+        move |mut caller: Caller<'_, T>, ptr: i32, offset: i32, length: i32| -> Result<i32> {
+            let bytes = take(get(caller.data_mut()));
+            let written = bytes.len().min(length as u32 as usize);
+            let memory = memory(&mut caller)?;
+            memory.write(&mut caller, address(ptr, offset), &bytes[..written])?;
+            Ok(written as i32)
+        },
+    )?;
+    Ok(())
+}
+
+/// Defines `name`, handing the JSON at `ptr + offset` to `deliver`. An empty
+/// value, what `JSON.stringify(undefined)` encodes to, is `Null`.
+fn define_writer<T: 'static, H: NodeRedHost + 'static>(
+    linker: &mut Linker<T>,
+    name: &'static str,
+    get: impl Fn(&mut T) -> &mut NodeRedCtx<H> + Send + Sync + Copy + 'static,
+    deliver: fn(&mut H, Value) -> Result<()>,
+) -> Result<()> {
+    linker.func_wrap(
+        IMPORT_MODULE,
+        name,
+        move |mut caller: Caller<'_, T>, ptr: i32, offset: i32, length: i32| -> Result<()> {
+            let memory = memory(&mut caller)?;
+            // Checked against the memory before anything is read or allocated
+            let start = address(ptr, offset);
+            let bytes = start
+                .checked_add(length as u32 as usize)
+                .and_then(|end| memory.data(&caller).get(start..end))
+                .ok_or_else(|| anyhow!("The value passed to {} is out of bounds", name))?;
+            let value = if bytes.is_empty() {
+                Value::Null
+            } else {
+                serde_json::from_slice(bytes)
+                    .map_err(|e| anyhow!("Invalid JSON passed to {}: {}", name, e))?
+            };
+            deliver(&mut get(caller.data_mut()).host, value)
+        },
+    )?;
+    Ok(())
+}
+
+fn memory<T>(caller: &mut Caller<'_, T>) -> Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| anyhow!("The module doesn't export its memory"))
+}
+
+fn address(ptr: i32, offset: i32) -> usize {
+    ptr as u32 as usize + offset as u32 as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_to_linker, check_instance, check_module, Event, NodeRedCtx, Recorder};
+    use anyhow::Result;
+    use serde_json::{json, Value};
+    use wasmtime::{Engine, Linker, Module, Store};
+
+    // Sends the first input back and takes it off the queue, then emits a
+    // status
+    const FORWARD: &str = r#"
+        (module
+            (import "node_red_v1" "msg_size" (func $msg_size (result i32)))
+            (import "node_red_v1" "msg" (func $msg (param i32 i32 i32) (result i32)))
+            (import "node_red_v1" "send" (func $send (param i32 i32 i32)))
+            (import "node_red_v1" "pop_size" (func $pop_size (result i32)))
+            (import "node_red_v1" "pop" (func $pop (param i32 i32 i32) (result i32)))
+            (import "node_red_v1" "emit" (func $emit (param i32 i32 i32)))
+            (import "node_red_v1" "done" (func $done (param i32 i32 i32)))
+            (memory (export "memory") 1)
+            (func (export "node_red_abi_version") (result i32) (i32.const 1))
+            (data (i32.const 0) "{\"event\":\"status\",\"payload\":{\"fill\":\"green\"}}")
+            (func (export "forward")
+                (local $length i32)
+                (local.set $length (call $msg_size))
+                (drop (call $msg (i32.const 1024) (i32.const 0) (local.get $length)))
+                (call $send (i32.const 1024) (i32.const 0) (local.get $length))
+                (local.set $length (call $pop_size))
+                (drop (call $pop (i32.const 1024) (i32.const 16) (local.get $length)))
+                (call $emit (i32.const 0) (i32.const 0) (i32.const 45))
+                (call $done (i32.const 0) (i32.const 0) (i32.const 0))))
+    "#;
+
+    #[test]
+    fn test_node_red_host() -> Result<()> {
+        let engine = Engine::default();
+        let mut linker: Linker<NodeRedCtx<Recorder>> = Linker::new(&engine);
+        add_to_linker(&mut linker, |ctx| ctx)?;
+        let module = Module::new(&engine, FORWARD)?;
+        check_module(&module)?;
+
+        let mut ctx = NodeRedCtx::new(Recorder::default());
+        ctx.push_input(json!({ "payload": 1 }));
+        ctx.push_input(json!({ "payload": "two" }));
+        let mut store = Store::new(&engine, ctx);
+        let instance = linker.instantiate(&mut store, &module)?;
+        check_instance(&mut store, &instance)?;
+        let forward = instance.get_typed_func::<(), ()>(&mut store, "forward")?;
+        for _ in 0..3 {
+            forward.call(&mut store, ())?;
+        }
+
+        let status = Event::Emit {
+            event: "status".to_string(),
+            payload: json!({ "fill": "green" }),
+        };
+        assert_eq!(
+            store.into_data().into_host().events,
+            vec![
+                Event::Send(json!({ "payload": 1 })),
+                status.clone(),
+                Event::Done(Value::Null),
+                Event::Send(json!({ "payload": "two" })),
+                status.clone(),
+                Event::Done(Value::Null),
+                // The queue is empty
+                Event::Send(Value::Null),
+                status,
+                Event::Done(Value::Null),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_out_of_bounds_value() -> Result<()> {
+        let engine = Engine::default();
+        let mut linker: Linker<NodeRedCtx<Recorder>> = Linker::new(&engine);
+        add_to_linker(&mut linker, |ctx| ctx)?;
+        let module = Module::new(
+            &engine,
+            r#"
+            (module
+                (import "node_red_v1" "send" (func $send (param i32 i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "send") (param $offset i32) (param $length i32)
+                    (call $send (i32.const 0) (local.get $offset) (local.get $length))))
+            "#,
+        )?;
+        let mut store = Store::new(&engine, NodeRedCtx::new(Recorder::default()));
+        let instance = linker.instantiate(&mut store, &module)?;
+        let send = instance.get_typed_func::<(i32, i32), ()>(&mut store, "send")?;
+
+        // Past the end of the one page of memory, and 4 GiB
+        assert!(send.call(&mut store, (65535, 2)).is_err());
+        assert!(send.call(&mut store, (0, -1)).is_err());
+        assert!(store.into_data().into_host().events.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_incompatible_modules() -> Result<()> {
+        let engine = Engine::default();
+        let check = |wat: &str| {
+            check_module(&Module::new(&engine, wat).unwrap())
+                .unwrap_err()
+                .to_string()
+        };
+
+        let unversioned = r#"(module (import "env" "node_red_send" (func (param i32 i32 i32))))"#;
+        assert!(check(unversioned).contains("older than the node_red_v1 ABI"));
+        let newer = r#"(module (import "node_red_v2" "send" (func (param i32 i32 i32))))"#;
+        assert!(check(newer).contains("built for the node_red_v2 ABI"));
+        let unknown = r#"(module (import "node_red_v1" "status" (func (param i32 i32 i32))))"#;
+        assert!(check(unknown).contains("not part of the ABI"));
+        let mistyped = r#"(module (import "node_red_v1" "msg_size" (func (result i64))))"#;
+        assert!(check(mistyped).contains("expected"));
+        // Other imports are left to the other host functions
+        check_module(&Module::new(
+            &engine,
+            r#"(module (import "env" "log" (func)))"#,
+        )?)?;
+
+        let mut linker: Linker<NodeRedCtx<Recorder>> = Linker::new(&engine);
+        add_to_linker(&mut linker, |ctx| ctx)?;
+        let mut store = Store::new(&engine, NodeRedCtx::new(Recorder::default()));
+        let version_2 =
+            r#"(module (func (export "node_red_abi_version") (result i32) (i32.const 2)))"#;
+        let instance = linker.instantiate(&mut store, &Module::new(&engine, version_2)?)?;
+        assert!(check_instance(&mut store, &instance).is_err());
+        Ok(())
+    }
+}